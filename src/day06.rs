@@ -1,6 +1,8 @@
 #[macro_use]
 extern crate nom;
 
+use std::{io, usize};
+use std::cmp;
 use std::str::{self, FromStr};
 use nom::{IResult, digit, space};
 
@@ -14,7 +16,7 @@ named!(number(&[u8]) -> usize,
     )
 );
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     TurnOn,
     TurnOff,
@@ -55,6 +57,29 @@ named!(instruction(&[u8]) -> Instruction,
     )
 );
 
+impl Instruction {
+    fn check_bounds(&self, width: usize, height: usize) -> Result<(), String> {
+        if self.x1 > self.x2 || self.y1 > self.y2 || self.x2 >= width || self.y2 >= height {
+            Err(format!("instruction {:?} out of bounds of {}x{} grid", self, width, height))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x1 && x <= self.x2 && y >= self.y1 && y <= self.y2
+    }
+}
+
+fn parse_instructions(text: &str) -> Vec<Instruction> {
+    text.lines().map(|line| {
+        match instruction(line.as_bytes()) {
+            IResult::Done(rest, ins) if rest == [] => ins,
+            _ => panic!("invalid instruction in line '{}'", line),
+        }
+    }).collect()
+}
+
 pub trait Light {
     fn switch(&mut self, cmd: &Command);
     fn brightness(&self) -> usize;
@@ -92,45 +117,376 @@ impl Light for u8 {
     }
 }
 
-pub struct Grid<T>([[T; 1000]; 1000]);
+impl Light for u32 {
+    fn switch(&mut self, cmd: &Command) {
+        match cmd {
+            &Command::TurnOn => *self += 1,
+            &Command::TurnOff if *self > 0 => *self -= 1,
+            &Command::TurnOff => (),
+            &Command::Toggle => *self += 2,
+        }
+    }
+
+    fn brightness(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Dimmable light whose brightness doesn't exceed the given maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturating {
+    value: usize,
+    max: usize,
+}
+
+impl Saturating {
+    pub fn new(max: usize) -> Saturating {
+        Saturating { value: 0, max: max }
+    }
+}
+
+impl Light for Saturating {
+    fn switch(&mut self, cmd: &Command) {
+        match cmd {
+            &Command::TurnOn => self.value = (self.value + 1).min(self.max),
+            &Command::TurnOff if self.value > 0 => self.value -= 1,
+            &Command::TurnOff => (),
+            &Command::Toggle => self.value = (self.value + 2).min(self.max),
+        }
+    }
+
+    fn brightness(&self) -> usize {
+        self.value
+    }
+}
+
+/// Colour light that turns white when turned on, black when turned off and
+/// changes to its complementary colour when toggled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Light for Rgb {
+    fn switch(&mut self, cmd: &Command) {
+        match cmd {
+            &Command::TurnOn => *self = Rgb(255, 255, 255),
+            &Command::TurnOff => *self = Rgb(0, 0, 0),
+            &Command::Toggle => *self = Rgb(255 - self.0, 255 - self.1, 255 - self.2),
+        }
+    }
+
+    fn brightness(&self) -> usize {
+        self.0 as usize + self.1 as usize + self.2 as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Operation {
+    Set(usize),
+    Add(usize),
+    Sub(usize),
+    Invert,
+}
+
+named!(operation(&[u8]) -> Operation,
+    alt!(
+        chain!(tag!("set") ~ space ~ n: number, || Operation::Set(n)) |
+        chain!(tag!("add") ~ space ~ n: number, || Operation::Add(n)) |
+        chain!(tag!("sub") ~ space ~ n: number, || Operation::Sub(n)) |
+        value!(Operation::Invert, tag!("invert"))
+    )
+);
+
+impl Operation {
+    fn apply(&self, value: usize, max: usize) -> usize {
+        match *self {
+            Operation::Set(n) => n.min(max),
+            Operation::Add(n) => value.saturating_add(n).min(max),
+            Operation::Sub(n) => value.saturating_sub(n),
+            Operation::Invert => max - value,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rule {
+    Command(Command, Operation),
+    Max(usize),
+}
+
+named!(rule(&[u8]) -> Rule,
+    alt!(
+        chain!(cmd: command ~ space ~ tag!("=>") ~ space ~ op: operation, || Rule::Command(cmd, op)) |
+        chain!(tag!("max") ~ space ~ n: number, || Rule::Max(n))
+    )
+);
+
+/// Table that defines what each command does to the brightness of a light,
+/// one rule per line, e.g. `turn on => add 1`, `toggle => invert` or `max 9`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Semantics {
+    turn_on: Operation,
+    turn_off: Operation,
+    toggle: Operation,
+    max: usize,
+}
+
+impl FromStr for Semantics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Semantics, String> {
+        let (mut turn_on, mut turn_off, mut toggle, mut max) = (None, None, None, None);
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            match rule(line.trim().as_bytes()) {
+                IResult::Done(rest, Rule::Command(Command::TurnOn, op)) if rest == [] => turn_on = Some(op),
+                IResult::Done(rest, Rule::Command(Command::TurnOff, op)) if rest == [] => turn_off = Some(op),
+                IResult::Done(rest, Rule::Command(Command::Toggle, op)) if rest == [] => toggle = Some(op),
+                IResult::Done(rest, Rule::Max(n)) if rest == [] => max = Some(n),
+                _ => return Err(format!("invalid rule in line '{}'", line)),
+            }
+        }
+        match (turn_on, turn_off, toggle) {
+            (Some(turn_on), Some(turn_off), Some(toggle)) => {
+                let inverts = [&turn_on, &turn_off, &toggle].iter().any(|op| **op == Operation::Invert);
+                if inverts && max.is_none() {
+                    return Err("invert requires a max rule".to_owned());
+                }
+                Ok(Semantics { turn_on: turn_on, turn_off: turn_off, toggle: toggle, max: max.unwrap_or(usize::MAX) })
+            }
+            _ => Err("rules for turn on, turn off and toggle are required".to_owned()),
+        }
+    }
+}
+
+impl Semantics {
+    pub fn light(&self) -> TableLight {
+        TableLight { value: 0, semantics: self }
+    }
+}
+
+/// Light that follows the rules of a semantics table
+#[derive(Debug, Clone, Copy)]
+pub struct TableLight<'a> {
+    value: usize,
+    semantics: &'a Semantics,
+}
+
+impl<'a> Light for TableLight<'a> {
+    fn switch(&mut self, cmd: &Command) {
+        let op = match cmd {
+            &Command::TurnOn => &self.semantics.turn_on,
+            &Command::TurnOff => &self.semantics.turn_off,
+            &Command::Toggle => &self.semantics.toggle,
+        };
+        self.value = op.apply(self.value, self.semantics.max);
+    }
+
+    fn brightness(&self) -> usize {
+        self.value
+    }
+}
+
+/// Maximum length of a line in plain PGM images
+const PGM_LINE_LENGTH: usize = 70;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    lights: Vec<T>,
+}
 
-impl<T: Copy> Grid<T> {
-    fn new(default: T) -> Grid<T> {
-        Grid([[default; 1000]; 1000])
+impl<T: Clone> Grid<T> {
+    fn new(width: usize, height: usize, default: T) -> Grid<T> {
+        Grid { width: width, height: height, lights: vec![default; width * height] }
     }
 }
 
 impl<T: Light> Grid<T> {
     fn brightness(&self) -> usize {
-        self.0.iter().map(|line|
-            line.iter().map(|light| light.brightness()).sum::<usize>()
-        ).sum()
+        self.lights.iter().map(|light| light.brightness()).sum()
     }
 
-    fn change(&mut self, ins: &Instruction) {
+    fn rect(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Result<Vec<&T>, String> {
+        if x1 > x2 || y1 > y2 || x2 >= self.width || y2 >= self.height {
+            return Err(format!("rectangle {},{} through {},{} out of bounds of {}x{} grid", x1, y1, x2, y2, self.width, self.height));
+        }
+        Ok((y1..y2 + 1).flat_map(|y| &self.lights[y * self.width + x1 .. y * self.width + x2 + 1]).collect())
+    }
+
+    /// Total brightness of the lights in the given rectangle
+    pub fn brightness_in(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Result<usize, String> {
+        self.rect(x1, y1, x2, y2).map(|lights| lights.iter().map(|light| light.brightness()).sum())
+    }
+
+    /// Number of lights in the given rectangle that are lit at all
+    pub fn lit_in(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Result<usize, String> {
+        self.rect(x1, y1, x2, y2).map(|lights| lights.iter().filter(|light| light.brightness() > 0).count())
+    }
+
+    fn change(&mut self, ins: &Instruction) -> Result<(), String> {
+        try!(ins.check_bounds(self.width, self.height));
         for y in ins.y1 .. ins.y2 + 1 {
-            for x in ins.x1 .. ins.x2 + 1 {
-                self.0[y][x].switch(&ins.command);
+            for light in &mut self.lights[y * self.width + ins.x1 .. y * self.width + ins.x2 + 1] {
+                light.switch(&ins.command);
             }
         }
+        Ok(())
     }
 
     fn run_instructions(&mut self, text: &str) {
-        for line in text.lines() {
-            match instruction(line.as_bytes()) {
-                IResult::Done(rest, ref ins) if rest == [] => self.change(ins),
-                _ => panic!("invalid instruction in line '{}'", line),
+        for (line, ins) in text.lines().zip(parse_instructions(text)) {
+            if let Err(err) = self.change(&ins) {
+                panic!("{} in line '{}'", err, line);
+            }
+        }
+    }
+
+    /// Writes the grid as plain PGM image, with brightness scaled to grey levels
+    pub fn write_pgm<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let max = self.lights.iter().map(|light| light.brightness()).max().unwrap_or(0);
+        try!(write!(out, "P2\n{} {}\n255\n", self.width, self.height));
+        for line in self.lights.chunks(self.width) {
+            let mut len = 0;
+            for light in line {
+                let level = if max > 0 { light.brightness() * 255 / max } else { 0 }.to_string();
+                if len + 1 + level.len() > PGM_LINE_LENGTH {
+                    try!(out.write_all(b"\n"));
+                    len = 0;
+                } else if len > 0 {
+                    try!(out.write_all(b" "));
+                    len += 1;
+                }
+                try!(out.write_all(level.as_bytes()));
+                len += level.len();
+            }
+            try!(out.write_all(b"\n"));
+        }
+        Ok(())
+    }
+}
+
+/// Grid together with the list of instructions applied to it, so that any
+/// intermediate state can be replayed
+pub struct History<T> {
+    initial: Grid<T>,
+    instructions: Vec<Instruction>,
+}
+
+impl<T: Light + Clone> History<T> {
+    pub fn new(initial: Grid<T>) -> History<T> {
+        History { initial: initial, instructions: Vec::new() }
+    }
+
+    pub fn record(&mut self, ins: Instruction) -> Result<(), String> {
+        try!(ins.check_bounds(self.initial.width, self.initial.height));
+        self.instructions.push(ins);
+        Ok(())
+    }
+
+    pub fn record_instructions(&mut self, text: &str) {
+        for (line, ins) in text.lines().zip(parse_instructions(text)) {
+            if let Err(err) = self.record(ins) {
+                panic!("{} in line '{}'", err, line);
+            }
+        }
+    }
+
+    /// State of the grid after applying the first k instructions
+    pub fn state_after(&self, k: usize) -> Grid<T> {
+        let mut grid = self.initial.clone();
+        for ins in self.instructions.iter().take(k) {
+            grid.change(ins).unwrap();
+        }
+        grid
+    }
+
+    /// Index of the last instruction that touched the given light, if any
+    pub fn last_touched(&self, x: usize, y: usize) -> Option<usize> {
+        self.instructions.iter().rposition(|ins| ins.contains(x, y))
+    }
+}
+
+/// Number of compressed lights swept at once by `CompressedGrid`
+const BLOCK_LIGHTS: usize = 1 << 20;
+
+/// Grid that only tracks one light for each rectangle of cells that are
+/// switched identically, derived from the bounds of all instructions. The
+/// rectangles are swept in blocks of rows, applying every instruction once
+/// per block, so at most `BLOCK_LIGHTS` lights are kept in memory.
+pub struct CompressedGrid<T> {
+    xs: Vec<usize>,
+    ys: Vec<usize>,
+    default: T,
+    changes: Vec<(Command, usize, usize, usize, usize)>,
+}
+
+impl<T: Light + Clone> CompressedGrid<T> {
+    pub fn new(width: usize, height: usize, default: T, instructions: &[Instruction]) -> Result<CompressedGrid<T>, String> {
+        let mut xs = vec![0, width];
+        let mut ys = vec![0, height];
+        for ins in instructions {
+            try!(ins.check_bounds(width, height));
+            xs.push(ins.x1);
+            xs.push(ins.x2 + 1);
+            ys.push(ins.y1);
+            ys.push(ins.y2 + 1);
+        }
+        xs.sort();
+        xs.dedup();
+        ys.sort();
+        ys.dedup();
+        let changes = instructions.iter().map(|ins| (
+            ins.command,
+            xs.binary_search(&ins.x1).unwrap(),
+            xs.binary_search(&(ins.x2 + 1)).unwrap(),
+            ys.binary_search(&ins.y1).unwrap(),
+            ys.binary_search(&(ins.y2 + 1)).unwrap(),
+        )).collect();
+        Ok(CompressedGrid { xs: xs, ys: ys, default: default, changes: changes })
+    }
+
+    pub fn from_instructions(width: usize, height: usize, default: T, text: &str) -> CompressedGrid<T> {
+        match CompressedGrid::new(width, height, default, &parse_instructions(text)) {
+            Ok(grid) => grid,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn brightness(&self) -> usize {
+        let width = self.xs.len() - 1;
+        let height = self.ys.len() - 1;
+        let rows = cmp::max(1, BLOCK_LIGHTS / width);
+        let mut block = Vec::with_capacity(cmp::min(rows, height) * width);
+        let mut total = 0;
+        for top in (0..height).step_by(rows) {
+            let bottom = cmp::min(top + rows, height);
+            block.clear();
+            block.resize((bottom - top) * width, self.default.clone());
+            for &(cmd, x1, x2, y1, y2) in &self.changes {
+                for y in cmp::max(y1, top)..cmp::min(y2, bottom) {
+                    let row = (y - top) * width;
+                    for light in &mut block[row + x1..row + x2] {
+                        light.switch(&cmd);
+                    }
+                }
+            }
+            for (y, row) in (top..bottom).zip(block.chunks(width)) {
+                let brightness: usize = row.iter().enumerate().map(|(x, light)| {
+                    light.brightness() * (self.xs[x + 1] - self.xs[x])
+                }).sum();
+                total += brightness * (self.ys[y + 1] - self.ys[y]);
             }
         }
+        total
     }
 }
 
 fn main() {
     let input = include_str!("day06.txt");
-    let mut grid = Grid::new(false);
+    let mut grid = Grid::new(1000, 1000, false);
     grid.run_instructions(input);
     println!("Number of lit lights: {}", grid.brightness());
-    let mut grid = Grid::new(0);
+    let mut grid = Grid::new(1000, 1000, 0u8);
     grid.run_instructions(input);
     println!("Total brightness of all lights: {}", grid.brightness());
 }
@@ -141,29 +497,186 @@ mod tests {
 
     #[test]
     fn switching_lights() {
-        let mut grid = Grid::new(false);
+        let mut grid = Grid::new(1000, 1000, false);
         assert_eq!(grid.brightness(), 0);
-        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 999, y2: 999 });
+        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 999, y2: 999 }).unwrap();
         assert_eq!(grid.brightness(), 1_000_000);
-        grid.change(&Instruction { command: Command::Toggle, x1: 0, y1: 0, x2: 999, y2: 0 });
+        grid.change(&Instruction { command: Command::Toggle, x1: 0, y1: 0, x2: 999, y2: 0 }).unwrap();
         assert_eq!(grid.brightness(), 999_000);
-        grid.change(&Instruction { command: Command::TurnOff, x1: 499, y1: 499, x2: 500, y2: 500 });
+        grid.change(&Instruction { command: Command::TurnOff, x1: 499, y1: 499, x2: 500, y2: 500 }).unwrap();
         assert_eq!(grid.brightness(), 998_996);
     }
 
     #[test]
     fn dimming_lights() {
-        let mut grid = Grid::new(0u8);
+        let mut grid = Grid::new(1000, 1000, 0u8);
         assert_eq!(grid.brightness(), 0);
-        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 0, y2: 0 });
+        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 0, y2: 0 }).unwrap();
         assert_eq!(grid.brightness(), 1);
-        grid.change(&Instruction { command: Command::Toggle, x1: 0, y1: 0, x2: 999, y2: 999 });
+        grid.change(&Instruction { command: Command::Toggle, x1: 0, y1: 0, x2: 999, y2: 999 }).unwrap();
         assert_eq!(grid.brightness(), 2_000_001);
     }
 
+    #[test]
+    fn sizing_grid() {
+        let mut grid = Grid::new(3, 2, false);
+        assert_eq!(grid.lights.len(), 6);
+        grid.change(&Instruction { command: Command::TurnOn, x1: 1, y1: 0, x2: 2, y2: 1 }).unwrap();
+        assert_eq!(grid.brightness(), 4);
+        assert!(grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 3, y2: 0 }).is_err());
+        assert!(grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 0, y2: 2 }).is_err());
+        assert_eq!(grid.brightness(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "in line 'turn on 0,0 through 10,10'")]
+    fn running_instructions_out_of_bounds() {
+        let mut grid = Grid::new(10, 10, false);
+        grid.run_instructions("turn on 0,0 through 10,10");
+    }
+
+    #[test]
+    fn exporting_pgm() {
+        let mut grid = Grid::new(3, 2, false);
+        grid.change(&Instruction { command: Command::TurnOn, x1: 1, y1: 0, x2: 2, y2: 0 }).unwrap();
+        let mut pgm = Vec::new();
+        grid.write_pgm(&mut pgm).unwrap();
+        assert_eq!(String::from_utf8(pgm).unwrap(), "P2\n3 2\n255\n0 255 255\n0 0 0\n");
+        let mut grid = Grid::new(3, 2, 0u8);
+        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 2, y2: 1 }).unwrap();
+        grid.change(&Instruction { command: Command::Toggle, x1: 2, y1: 1, x2: 2, y2: 1 }).unwrap();
+        grid.change(&Instruction { command: Command::TurnOff, x1: 0, y1: 0, x2: 0, y2: 0 }).unwrap();
+        let mut pgm = Vec::new();
+        grid.write_pgm(&mut pgm).unwrap();
+        assert_eq!(String::from_utf8(pgm).unwrap(), "P2\n3 2\n255\n0 85 85\n85 85 255\n");
+        let mut grid = Grid::new(20, 1, false);
+        grid.change(&Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 18, y2: 0 }).unwrap();
+        let mut pgm = Vec::new();
+        grid.write_pgm(&mut pgm).unwrap();
+        let pgm = String::from_utf8(pgm).unwrap();
+        assert_eq!(pgm, format!("P2\n20 1\n255\n{}255\n255 255 0\n", "255 ".repeat(16)));
+        assert!(pgm.lines().all(|line| line.len() <= 70));
+    }
+
+    #[test]
+    fn more_lights() {
+        let input = "turn on 0,0 through 9,9\ntoggle 0,0 through 4,4\ntoggle 0,0 through 4,4\nturn off 0,0 through 0,9";
+        let mut grid = Grid::new(10, 10, 0u32);
+        grid.run_instructions(input);
+        assert_eq!(grid.brightness(), 190);
+        let mut grid = Grid::new(10, 10, Saturating::new(3));
+        grid.run_instructions(input);
+        assert_eq!(grid.brightness(), 140);
+        let mut grid = Grid::new(10, 10, Rgb(255, 0, 0));
+        grid.run_instructions("toggle 0,0 through 9,9\nturn on 0,0 through 0,9\nturn off 1,0 through 1,9");
+        assert_eq!(grid.lights[0], Rgb(255, 255, 255));
+        assert_eq!(grid.lights[1], Rgb(0, 0, 0));
+        assert_eq!(grid.lights[2], Rgb(0, 255, 255));
+        assert_eq!(grid.brightness(), 10 * 765 + 80 * 510);
+    }
+
+    #[test]
+    fn parsing_semantics() {
+        assert_eq!(rule(b"turn on => add 1").unwrap(), (&b""[..], Rule::Command(Command::TurnOn, Operation::Add(1))));
+        assert_eq!(rule(b"toggle => invert").unwrap(), (&b""[..], Rule::Command(Command::Toggle, Operation::Invert)));
+        assert_eq!(rule(b"max 9").unwrap(), (&b""[..], Rule::Max(9)));
+        assert_eq!("turn on => set 1\nturn off => set 0\ntoggle => invert\nmax 1".parse(), Ok(Semantics {
+            turn_on: Operation::Set(1), turn_off: Operation::Set(0), toggle: Operation::Invert, max: 1,
+        }));
+        assert!("turn on => set 1\ntoggle => invert".parse::<Semantics>().is_err());
+        assert!("turn on => double\nturn off => set 0\ntoggle => invert".parse::<Semantics>().is_err());
+        assert_eq!("turn on => add 1\nturn off => sub 1\ntoggle => invert".parse::<Semantics>(),
+                   Err("invert requires a max rule".to_owned()));
+    }
+
+    #[test]
+    fn table_lights() {
+        let input = include_str!("day06.txt");
+        let semantics: Semantics = "turn on => set 1\nturn off => set 0\ntoggle => invert\nmax 1".parse().unwrap();
+        let mut grid = Grid::new(1000, 1000, semantics.light());
+        grid.run_instructions(input);
+        let mut expected = Grid::new(1000, 1000, false);
+        expected.run_instructions(input);
+        assert_eq!(grid.brightness(), expected.brightness());
+        let semantics: Semantics = "turn on => add 1\nturn off => sub 1\ntoggle => add 2".parse().unwrap();
+        let mut grid = Grid::new(1000, 1000, semantics.light());
+        grid.run_instructions(input);
+        let mut expected = Grid::new(1000, 1000, 0u32);
+        expected.run_instructions(input);
+        assert_eq!(grid.brightness(), expected.brightness());
+    }
+
+    #[test]
+    fn querying_rectangles() {
+        let mut grid = Grid::new(1000, 1000, 0u8);
+        grid.run_instructions("turn on 0,0 through 9,9\ntoggle 5,5 through 14,14");
+        assert_eq!(grid.brightness_in(0, 0, 999, 999), Ok(grid.brightness()));
+        assert_eq!(grid.brightness_in(0, 0, 4, 4), Ok(25));
+        assert_eq!(grid.brightness_in(5, 5, 9, 9), Ok(75));
+        assert_eq!(grid.brightness_in(10, 0, 14, 14), Ok(100));
+        assert_eq!(grid.lit_in(0, 0, 14, 14), Ok(175));
+        assert_eq!(grid.lit_in(15, 15, 15, 15), Ok(0));
+        assert!(grid.lit_in(0, 0, 1000, 0).is_err());
+        assert!(grid.brightness_in(5, 0, 4, 0).is_err());
+    }
+
+    #[test]
+    fn replaying_history() {
+        let mut history = History::new(Grid::new(10, 10, false));
+        history.record_instructions("turn on 0,0 through 9,9\ntoggle 0,0 through 9,0\nturn off 4,4 through 5,5");
+        assert!(history.record(Instruction { command: Command::TurnOn, x1: 0, y1: 0, x2: 10, y2: 0 }).is_err());
+        assert_eq!(history.state_after(0).brightness(), 0);
+        assert_eq!(history.state_after(1).brightness(), 100);
+        assert_eq!(history.state_after(2).brightness(), 90);
+        assert_eq!(history.state_after(3).brightness(), 86);
+        assert_eq!(history.state_after(4).brightness(), 86);
+        assert_eq!(history.last_touched(3, 0), Some(1));
+        assert_eq!(history.last_touched(5, 4), Some(2));
+        assert_eq!(history.last_touched(9, 9), Some(0));
+        let history = History::new(Grid::new(10, 10, false));
+        assert_eq!(history.last_touched(0, 0), None);
+    }
+
+    #[test]
+    fn compressing_grid() {
+        let instructions = parse_instructions("turn on 0,0 through 999,999\ntoggle 0,0 through 999,0\nturn off 499,499 through 500,500");
+        let grid = CompressedGrid::new(1000, 1000, false, &instructions).unwrap();
+        assert_eq!((grid.xs.len() - 1) * (grid.ys.len() - 1), 12);
+        assert_eq!(grid.brightness(), 998_996);
+        let grid = CompressedGrid::new(1_000_000, 1_000_000, 0u8, &instructions).unwrap();
+        assert_eq!(grid.brightness(), 1_001_996);
+        assert!(CompressedGrid::new(500, 1000, false, &instructions).is_err());
+    }
+
+    #[test]
+    fn compressed_matches_naive() {
+        let commands = ["turn on", "turn off", "toggle"];
+        let mut seed = 42usize;
+        let mut rand = |n: usize| { seed = (seed * 1103515245 + 12345) % 2147483648; seed / 65536 % n };
+        // (instructions, width, height, largest rectangle side)
+        for &(count, width, height, side) in &[(200, 60, 40, 60), (12_000, 300, 200, 300), (12_000, 2000, 1000, 20)] {
+            let mut text = String::new();
+            for _ in 0..count {
+                let (x1, y1) = (rand(width), rand(height));
+                let (x2, y2) = (x1 + rand(side.min(width - x1)), y1 + rand(side.min(height - y1)));
+                text.push_str(&format!("{} {},{} through {},{}\n", commands[rand(3)], x1, y1, x2, y2));
+            }
+            let mut grid = Grid::new(width, height, false);
+            grid.run_instructions(&text);
+            assert_eq!(CompressedGrid::from_instructions(width, height, false, &text).brightness(), grid.brightness());
+            let mut grid = Grid::new(width, height, 0u32);
+            grid.run_instructions(&text);
+            assert_eq!(CompressedGrid::from_instructions(width, height, 0u32, &text).brightness(), grid.brightness());
+        }
+        let input = include_str!("day06.txt");
+        let mut grid = Grid::new(1000, 1000, false);
+        grid.run_instructions(input);
+        assert_eq!(CompressedGrid::from_instructions(1000, 1000, false, input).brightness(), grid.brightness());
+    }
+
     #[test]
     fn running_instructions() {
-        let mut grid = Grid::new(false);
+        let mut grid = Grid::new(1000, 1000, false);
         grid.run_instructions("turn on 0,0 through 999,999\ntoggle 0,0 through 999,0\nturn off 499,499 through 500,500");
         assert_eq!(grid.brightness(), 998_996);
     }
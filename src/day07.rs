@@ -2,10 +2,22 @@
 extern crate nom;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::ops::{Not, BitAnd, BitOr, Shl, Shr};
 use std::str::{self, FromStr};
 use nom::{IResult, alpha, digit, space};
 
+pub trait Signal: Copy + fmt::Debug + fmt::Display + Eq + FromStr + Not<Output=Self> + BitAnd<Output=Self> + BitOr<Output=Self> + Shl<u8, Output=Self> + Shr<u8, Output=Self> {
+    fn bits() -> u8;
+    fn from_u64(n: u64) -> Self;
+}
+
+impl Signal for u8 { fn bits() -> u8 { 8 } fn from_u64(n: u64) -> u8 { n as u8 } }
+impl Signal for u16 { fn bits() -> u8 { 16 } fn from_u64(n: u64) -> u16 { n as u16 } }
+impl Signal for u32 { fn bits() -> u8 { 32 } fn from_u64(n: u64) -> u32 { n as u32 } }
+impl Signal for u64 { fn bits() -> u8 { 64 } fn from_u64(n: u64) -> u64 { n } }
+
 named!(number(&[u8]) -> usize,
     map_res!(
         map_res!(
@@ -23,31 +35,100 @@ named!(wire(&[u8]) -> &str,
     )
 );
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Value<'a> {
-    Signal(u16),
+fn signal<T: Signal>(input: &[u8]) -> IResult<&[u8], T> {
+    map_res!(input,
+        map_res!(
+            digit,
+            str::from_utf8
+        ),
+        T::from_str
+    )
+}
+
+fn shift<T: Signal>(input: &[u8]) -> IResult<&[u8], u8> {
+    map_res!(input,
+        number,
+        |n| if n < T::bits() as usize { Ok(n as u8) } else { Err(()) }
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a, T> {
+    Signal(T),
     Wire(&'a str),
 }
 
-named!(pub value(&[u8]) -> Value,
-    alt!(
-        map!(number, |n| Value::Signal(n as u16))
+impl<'a, T: fmt::Display> fmt::Display for Value<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Signal(ref s) => s.fmt(f),
+            Value::Wire(w) => f.write_str(w),
+        }
+    }
+}
+
+pub fn value<T: Signal>(input: &[u8]) -> IResult<&[u8], Value<T>> {
+    alt!(input,
+        map!(signal, |s| Value::Signal(s))
         | map!(wire, |s| Value::Wire(s))
     )
-);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression<'a, T> {
+    Not(Value<'a, T>),
+    And(Value<'a, T>, Value<'a, T>),
+    Or(Value<'a, T>, Value<'a, T>),
+    LShift(Value<'a, T>, u8),
+    RShift(Value<'a, T>, u8),
+    Value(Value<'a, T>),
+}
+
+impl<'a, T> Expression<'a, T> {
+    fn wires(&self) -> Vec<&'a str> {
+        let values = match *self {
+            Expression::Not(ref v) => vec![v],
+            Expression::And(ref v1, ref v2) => vec![v1, v2],
+            Expression::Or(ref v1, ref v2) => vec![v1, v2],
+            Expression::LShift(ref v, _) => vec![v],
+            Expression::RShift(ref v, _) => vec![v],
+            Expression::Value(ref v) => vec![v],
+        };
+        values.into_iter().filter_map(|v| match *v {
+            Value::Wire(w) => Some(w),
+            Value::Signal(_) => None,
+        }).collect()
+    }
+}
+
+impl<'a, T: Copy> Expression<'a, T> {
+    fn map_values<F: FnMut(&Value<'a, T>) -> Value<'a, T>>(&self, mut f: F) -> Expression<'a, T> {
+        match *self {
+            Expression::Not(ref v) => Expression::Not(f(v)),
+            Expression::And(ref v1, ref v2) => Expression::And(f(v1), f(v2)),
+            Expression::Or(ref v1, ref v2) => Expression::Or(f(v1), f(v2)),
+            Expression::LShift(ref v, n) => Expression::LShift(f(v), n),
+            Expression::RShift(ref v, n) => Expression::RShift(f(v), n),
+            Expression::Value(ref v) => Expression::Value(f(v)),
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Expression<'a> {
-    Not(Value<'a>),
-    And(Value<'a>, Value<'a>),
-    Or(Value<'a>, Value<'a>),
-    LShift(Value<'a>, u8),
-    RShift(Value<'a>, u8),
-    Value(Value<'a>),
+impl<'a, T: fmt::Display> fmt::Display for Expression<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Not(ref v) => write!(f, "NOT {}", v),
+            Expression::And(ref v1, ref v2) => write!(f, "{} AND {}", v1, v2),
+            Expression::Or(ref v1, ref v2) => write!(f, "{} OR {}", v1, v2),
+            Expression::LShift(ref v, n) => write!(f, "{} LSHIFT {}", v, n),
+            Expression::RShift(ref v, n) => write!(f, "{} RSHIFT {}", v, n),
+            Expression::Value(ref v) => v.fmt(f),
+        }
+    }
 }
 
-named!(pub expression(&[u8]) -> Expression,
-    alt!(
+pub fn expression<T: Signal>(input: &[u8]) -> IResult<&[u8], Expression<T>> {
+    alt!(input,
         chain!(
             tag!("NOT") ~
             space ~
@@ -72,24 +153,24 @@ named!(pub expression(&[u8]) -> Expression,
             space ~
             tag!("LSHIFT") ~
             space ~
-            n: number,
-            || Expression::LShift(v, n as u8)
+            n: call!(shift::<T>),
+            || Expression::LShift(v, n)
         ) | chain!(
             v: value ~
             space ~
             tag!("RSHIFT") ~
             space ~
-            n: number,
-            || Expression::RShift(v, n as u8)
+            n: call!(shift::<T>),
+            || Expression::RShift(v, n)
         ) | chain!(
             v: value,
             || Expression::Value(v)
         )
     )
-);
+}
 
-named!(pub assignment(&[u8]) -> (&str, Expression),
-    complete!(
+pub fn assignment<T: Signal>(input: &[u8]) -> IResult<&[u8], (&str, Expression<T>)> {
+    complete!(input,
         chain!(
             e: expression ~
             space ~
@@ -99,33 +180,48 @@ named!(pub assignment(&[u8]) -> (&str, Expression),
             || (w, e)
         )
     )
-);
+}
 
-pub struct Circuit<'a> {
-    wires: HashMap<&'a str, Expression<'a>>,
-    cache: RefCell<HashMap<&'a str, u16>>,
+/// Number of bit assignments after which `Circuit::solve` gives up
+const MAX_SOLVE_STEPS: usize = 1 << 20;
+
+pub struct Circuit<'a, T> {
+    wires: HashMap<&'a str, Expression<'a, T>>,
+    outputs: HashMap<&'a str, Vec<&'a str>>,
+    registers: HashMap<&'a str, T>,
+    cache: RefCell<HashMap<&'a str, T>>,
 }
 
-impl<'a> Circuit<'a> {
-    fn new(definitions: &str) -> Circuit {
-        let mut circ = Circuit { wires: HashMap::new(), cache: RefCell::new(HashMap::new()) };
+impl<'a, T: Signal> Circuit<'a, T> {
+    fn new(definitions: &'a str) -> Circuit<'a, T> {
+        let mut wires = HashMap::new();
         for line in definitions.lines() {
             match assignment(line.as_bytes()) {
-                IResult::Done(_, (wire, expr)) => { circ.wires.insert(wire, expr); },
+                IResult::Done(_, (wire, expr)) => { wires.insert(wire, expr); },
                 _ => panic!("invalid assignment in line '{}'", line),
             }
         }
-        circ
+        Circuit::from_wires(wires)
     }
 
-    fn eval_value(&self, value: &Value<'a>) -> u16 {
+    fn from_wires(wires: HashMap<&'a str, Expression<'a, T>>) -> Circuit<'a, T> {
+        let mut outputs = HashMap::new();
+        for (&w, expr) in &wires {
+            for input in expr.wires() {
+                outputs.entry(input).or_insert_with(Vec::new).push(w);
+            }
+        }
+        Circuit { wires: wires, outputs: outputs, registers: HashMap::new(), cache: RefCell::new(HashMap::new()) }
+    }
+
+    fn eval_value(&self, value: &Value<'a, T>) -> T {
         match *value {
             Value::Signal(s) => s,
             Value::Wire(w) => self.eval(w),
         }
     }
 
-    fn eval_expression(&self, expr: &Expression<'a>) -> u16 {
+    fn eval_expression(&self, expr: &Expression<'a, T>) -> T {
         match *expr {
             Expression::Not(ref v) => !self.eval_value(v),
             Expression::And(ref v1, ref v2) => self.eval_value(v1) & self.eval_value(v2),
@@ -136,7 +232,10 @@ impl<'a> Circuit<'a> {
         }
     }
 
-    fn eval(&self, wire: &'a str) -> u16 {
+    fn eval(&self, wire: &'a str) -> T {
+        if let Some(state) = self.registers.get(wire) {
+            return *state
+        }
         if let Some(res) = self.cache.borrow().get(wire) {
             return *res
         }
@@ -150,14 +249,287 @@ impl<'a> Circuit<'a> {
         }
     }
 
-    fn set(&mut self, wire: &'a str, signal: u16) {
-        self.wires.insert(wire, Expression::Value(Value::Signal(signal)));
-        self.cache.borrow_mut().clear();
+    fn invalidate(&self, wire: &'a str) {
+        let mut cache = self.cache.borrow_mut();
+        cache.remove(wire);
+        for w in self.affected(wire) {
+            cache.remove(w);
+        }
+    }
+
+    fn define(&mut self, wire: &'a str, expr: Expression<'a, T>) -> Option<Expression<'a, T>> {
+        self.invalidate(wire);
+        for input in expr.wires() {
+            self.outputs.entry(input).or_insert_with(Vec::new).push(wire);
+        }
+        let old = self.wires.insert(wire, expr);
+        if let Some(ref expr) = old {
+            for input in expr.wires() {
+                if let Some(outputs) = self.outputs.get_mut(input) {
+                    if let Some(pos) = outputs.iter().position(|&w| w == wire) {
+                        outputs.remove(pos);
+                    }
+                }
+            }
+        }
+        old
+    }
+
+    fn set(&mut self, wire: &'a str, signal: T) {
+        self.define(wire, Expression::Value(Value::Signal(signal)));
+    }
+
+    /// Overrides several wires at once and evaluates the given wires afterwards
+    pub fn set_all(&mut self, overrides: &[(&'a str, T)], wires: &[&'a str]) -> Vec<T> {
+        for &(wire, signal) in overrides {
+            self.set(wire, signal);
+        }
+        wires.iter().map(|&w| self.eval(w)).collect()
+    }
+
+    /// Declares the given wire as a register with the given initial signal.
+    /// A register provides its latched signal and only takes over the signal
+    /// of its expression on the next clock tick, which allows feedback loops.
+    pub fn register(&mut self, wire: &'a str, initial: T) {
+        if !self.wires.contains_key(wire) {
+            panic!("unknown wire '{}'", wire);
+        }
+        self.registers.insert(wire, initial);
+        self.invalidate(wire);
+    }
+
+    /// Advances the clock by one tick, latching new signals into all registers
+    pub fn step(&mut self) {
+        let next: Vec<(&'a str, T)> = self.registers.keys().map(|&r| {
+            (r, self.eval_expression(&self.wires[r]))
+        }).collect();
+        for (r, signal) in next {
+            self.registers.insert(r, signal);
+            self.invalidate(r);
+        }
+    }
+
+    /// Runs the given number of clock ticks and returns the signals of the
+    /// given wires as seen during each tick
+    pub fn run(&mut self, ticks: usize, wires: &[&'a str]) -> Vec<Vec<T>> {
+        (0..ticks).map(|_| {
+            let signals = wires.iter().map(|&w| self.eval(w)).collect();
+            self.step();
+            signals
+        }).collect()
+    }
+
+    /// Evaluates the given wire with only some bits of the free wires known,
+    /// returning a mask of the known bits of its signal along with their values.
+    /// Wires that don't depend on any free wire are evaluated as usual.
+    fn eval_partial(&self, wire: &'a str, free: &HashMap<&'a str, (T, T)>, dependent: &HashSet<&'a str>, done: &mut HashMap<&'a str, (T, T)>) -> (T, T) {
+        let all = !T::from_u64(0);
+        if let Some(&partial) = free.get(wire) {
+            return partial;
+        }
+        if let Some(&partial) = done.get(wire) {
+            return partial;
+        }
+        if self.registers.contains_key(wire) || !dependent.contains(wire) {
+            return (all, self.eval(wire));
+        }
+        let mut value = |v: &Value<'a, T>| match *v {
+            Value::Signal(s) => (all, s),
+            Value::Wire(w) => self.eval_partial(w, free, dependent, done),
+        };
+        let partial = match self.wires[wire] {
+            Expression::Not(ref v) => { let (k, s) = value(v); (k, !s & k) },
+            Expression::And(ref v1, ref v2) => {
+                let ((k1, s1), (k2, s2)) = (value(v1), value(v2));
+                ((k1 & k2) | (k1 & !s1) | (k2 & !s2), s1 & s2)
+            },
+            Expression::Or(ref v1, ref v2) => {
+                let ((k1, s1), (k2, s2)) = (value(v1), value(v2));
+                ((k1 & k2) | s1 | s2, s1 | s2)
+            },
+            Expression::LShift(ref v, n) => { let (k, s) = value(v); ((k << n) | !(all << n), s << n) },
+            Expression::RShift(ref v, n) => { let (k, s) = value(v); ((k >> n) | !(all >> n), s >> n) },
+            Expression::Value(ref v) => value(v),
+        };
+        done.insert(wire, partial);
+        partial
+    }
+
+    /// Assigns the remaining bits of the free wires one at a time, lowest bits
+    /// first, and backtracks as soon as a known bit of the target is wrong
+    fn solve_bits(&self, goal: (&'a str, T), wires: &[&'a str], pos: usize, free: &mut HashMap<&'a str, (T, T)>, dependent: &HashSet<&'a str>, steps: &mut usize) -> Result<bool, String> {
+        let (target, signal) = goal;
+        let (known, value) = self.eval_partial(target, free, dependent, &mut HashMap::new());
+        if value != signal & known {
+            return Ok(false);
+        }
+        if pos == wires.len() * T::bits() as usize {
+            return Ok(true);
+        }
+        *steps += 1;
+        if *steps > MAX_SOLVE_STEPS {
+            return Err(format!("no solution for wire '{}' found within {} steps", target, MAX_SOLVE_STEPS));
+        }
+        let wire = wires[pos % wires.len()];
+        let bit = T::from_u64(1) << (pos / wires.len()) as u8;
+        let (known, value) = free[wire];
+        for &signal_bit in &[T::from_u64(0), bit] {
+            free.insert(wire, (known | bit, value | signal_bit));
+            if try!(self.solve_bits(goal, wires, pos + 1, free, dependent, steps)) {
+                return Ok(true);
+            }
+        }
+        free.insert(wire, (known, value));
+        Ok(false)
+    }
+
+    /// Finds signals for the given free wires that make the target wire carry the
+    /// given signal. Free wires that the target doesn't depend on are left alone,
+    /// the others are assigned bit by bit, propagating the known bits through the
+    /// circuit to rule out assignments early. Returns None if there's no solution
+    /// and an error if a wire is unknown or the search doesn't finish within a
+    /// fixed number of steps.
+    pub fn solve(&self, target: &'a str, signal: T, free: &[&'a str]) -> Result<Option<Vec<T>>, String> {
+        for &w in [target].iter().chain(free) {
+            if !self.wires.contains_key(w) {
+                return Err(format!("unknown wire '{}'", w));
+            }
+        }
+        let inputs = self.inputs(target);
+        let mut relevant: Vec<&'a str> = free.iter().cloned().filter(|&w| w == target || inputs.contains(w)).collect();
+        relevant.sort();
+        relevant.dedup();
+        let mut dependent = HashSet::new();
+        for &w in &relevant {
+            dependent.extend(self.affected(w));
+        }
+        let mut assigned = relevant.iter().map(|&w| (w, (T::from_u64(0), T::from_u64(0)))).collect();
+        if !try!(self.solve_bits((target, signal), &relevant, 0, &mut assigned, &dependent, &mut 0)) {
+            return Ok(None);
+        }
+        Ok(Some(free.iter().map(|&w| match assigned.get(w) {
+            Some(&(_, s)) => s,
+            None => self.eval(w),
+        }).collect()))
+    }
+
+    /// Wires whose signals the given wire depends on, directly or indirectly
+    pub fn inputs(&self, wire: &'a str) -> HashSet<&'a str> {
+        let mut inputs = HashSet::new();
+        let mut todo = vec![wire];
+        while let Some(w) = todo.pop() {
+            match self.wires.get(w) {
+                Some(expr) => for input in expr.wires() {
+                    if inputs.insert(input) {
+                        todo.push(input);
+                    }
+                },
+                None => panic!("unknown wire '{}'", w),
+            }
+        }
+        inputs
+    }
+
+    /// Wires whose signals change if the given wire is overridden
+    pub fn affected(&self, wire: &'a str) -> HashSet<&'a str> {
+        let mut affected = HashSet::new();
+        let mut todo = vec![wire];
+        while let Some(w) = todo.pop() {
+            for &output in self.outputs.get(w).map(|o| &o[..]).unwrap_or(&[]) {
+                if affected.insert(output) {
+                    todo.push(output);
+                }
+            }
+        }
+        affected
+    }
+
+    fn simplify_wire(&self, wire: &'a str, inputs: &[&'a str], outputs: &[&'a str], done: &mut HashMap<&'a str, Expression<'a, T>>) -> Expression<'a, T> {
+        if let Some(expr) = done.get(wire) {
+            return expr.clone();
+        }
+        let expr = match self.wires.get(wire) {
+            Some(expr) => expr.map_values(|v| match *v {
+                Value::Wire(w) if !inputs.contains(&w) && !self.registers.contains_key(w) => match self.simplify_wire(w, inputs, outputs, done) {
+                    Expression::Value(Value::Signal(s)) => Value::Signal(s),
+                    Expression::Value(Value::Wire(a)) if !outputs.contains(&w) => Value::Wire(a),
+                    _ => Value::Wire(w),
+                },
+                ref v => v.clone(),
+            }),
+            None => panic!("unknown wire '{}'", wire),
+        };
+        let expr = match expr {
+            Expression::Value(_) => expr,
+            _ if expr.wires().is_empty() => Expression::Value(Value::Signal(self.eval_expression(&expr))),
+            _ => expr,
+        };
+        done.insert(wire, expr.clone());
+        expr
+    }
+
+    /// Equivalent circuit for the given outputs, with constants folded, aliases
+    /// collapsed and unused wires removed. Input wires are kept as they are, so
+    /// they can still be overridden.
+    pub fn simplify(&self, inputs: &[&'a str], outputs: &[&'a str]) -> Circuit<'a, T> {
+        let mut done = HashMap::new();
+        let mut wires = HashMap::new();
+        let mut todo = outputs.to_vec();
+        while let Some(w) = todo.pop() {
+            if !wires.contains_key(w) {
+                let expr = match self.simplify_wire(w, inputs, outputs, &mut done) {
+                    Expression::Value(Value::Wire(a)) if !inputs.contains(&a) && !outputs.contains(&a) =>
+                        self.simplify_wire(a, inputs, outputs, &mut done),
+                    expr => expr,
+                };
+                todo.extend(expr.wires());
+                wires.insert(w, expr);
+            }
+        }
+        let mut circ = Circuit::from_wires(wires);
+        for (&r, &state) in &self.registers {
+            if circ.wires.contains_key(r) {
+                circ.registers.insert(r, state);
+            }
+        }
+        circ
+    }
+
+    /// Graphviz representation of the circuit with gates as nodes and wires as edges
+    pub fn to_dot(&self) -> String {
+        let mut names: Vec<&str> = self.wires.keys().cloned().collect();
+        names.sort();
+        let mut dot = String::new();
+        dot.push_str("digraph circuit {\n");
+        for &w in &names {
+            writeln!(dot, "    \"{}\" [label=\"{} -> {}\"];", w, self.wires[w], w).unwrap();
+        }
+        for &w in &names {
+            for input in self.wires[w].wires() {
+                writeln!(dot, "    \"{}\" -> \"{}\" [label=\"{}\"];", input, w, input).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl<'a, T: Signal> fmt::Display for Circuit<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&str> = self.wires.keys().cloned().collect();
+        names.sort();
+        for (i, &w) in names.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str("\n"));
+            }
+            try!(write!(f, "{} -> {}", self.wires[w], w));
+        }
+        Ok(())
     }
 }
 
 fn main() {
-    let mut circ = Circuit::new(include_str!("day07.txt"));
+    let mut circ = Circuit::<u16>::new(include_str!("day07.txt"));
     let a1 = circ.eval("a");
     println!("Ultimate signal to wire a: {}", a1);
     circ.set("b", a1);
@@ -171,37 +543,48 @@ mod tests {
 
     #[test]
     fn parse_value() {
-        assert_eq!(value(b"123").unwrap(), (&b""[..], Value::Signal(123)));
-        assert_eq!(value(b"foo").unwrap(), (&b""[..], Value::Wire("foo")));
+        assert_eq!(value::<u16>(b"123").unwrap(), (&b""[..], Value::Signal(123)));
+        assert_eq!(value::<u16>(b"foo").unwrap(), (&b""[..], Value::Wire("foo")));
     }
 
     #[test]
     fn parse_expression() {
-        assert_eq!(expression(b"NOT foo").unwrap(), (&b""[..], Expression::Not(Value::Wire("foo"))));
-        assert_eq!(expression(b"foo AND bar").unwrap(), (&b""[..], Expression::And(Value::Wire("foo"), Value::Wire("bar"))));
-        assert_eq!(expression(b"foo OR bar").unwrap(), (&b""[..], Expression::Or(Value::Wire("foo"), Value::Wire("bar"))));
-        assert_eq!(expression(b"foo LSHIFT 3").unwrap(), (&b""[..], Expression::LShift(Value::Wire("foo"), 3)));
-        assert_eq!(expression(b"foo RSHIFT 4").unwrap(), (&b""[..], Expression::RShift(Value::Wire("foo"), 4)));
+        assert_eq!(expression::<u16>(b"NOT foo").unwrap(), (&b""[..], Expression::Not(Value::Wire("foo"))));
+        assert_eq!(expression::<u16>(b"foo AND bar").unwrap(), (&b""[..], Expression::And(Value::Wire("foo"), Value::Wire("bar"))));
+        assert_eq!(expression::<u16>(b"foo OR bar").unwrap(), (&b""[..], Expression::Or(Value::Wire("foo"), Value::Wire("bar"))));
+        assert_eq!(expression::<u16>(b"foo LSHIFT 3").unwrap(), (&b""[..], Expression::LShift(Value::Wire("foo"), 3)));
+        assert_eq!(expression::<u16>(b"foo RSHIFT 4").unwrap(), (&b""[..], Expression::RShift(Value::Wire("foo"), 4)));
     }
 
     #[test]
     fn parse_assignment() {
-        assert_eq!(assignment(b"123 -> x").unwrap(), (&b""[..], ("x", Expression::Value(Value::Signal(123)))));
-        assert_eq!(assignment(b"x AND y -> z").unwrap(), (&b""[..], ("z", Expression::And(Value::Wire("x"), Value::Wire("y")))));
-        assert_eq!(assignment(b"p LSHIFT 2 -> q").unwrap(), (&b""[..], ("q", Expression::LShift(Value::Wire("p"), 2))));
-        assert_eq!(assignment(b"NOT e -> f").unwrap(), (&b""[..], ("f", Expression::Not(Value::Wire("e")))));
+        assert_eq!(assignment::<u16>(b"123 -> x").unwrap(), (&b""[..], ("x", Expression::Value(Value::Signal(123)))));
+        assert_eq!(assignment::<u16>(b"x AND y -> z").unwrap(), (&b""[..], ("z", Expression::And(Value::Wire("x"), Value::Wire("y")))));
+        assert_eq!(assignment::<u16>(b"p LSHIFT 2 -> q").unwrap(), (&b""[..], ("q", Expression::LShift(Value::Wire("p"), 2))));
+        assert_eq!(assignment::<u16>(b"NOT e -> f").unwrap(), (&b""[..], ("f", Expression::Not(Value::Wire("e")))));
+    }
+
+    #[test]
+    fn parse_width() {
+        assert_eq!(value::<u8>(b"255").unwrap(), (&b""[..], Value::Signal(255)));
+        assert!(value::<u8>(b"256").is_err());
+        assert_eq!(value::<u32>(b"4294967295").unwrap(), (&b""[..], Value::Signal(4294967295)));
+        assert!(value::<u32>(b"4294967296").is_err());
+        assert_eq!(expression::<u64>(b"foo LSHIFT 40").unwrap(), (&b""[..], Expression::LShift(Value::Wire("foo"), 40)));
+        assert!(expression::<u16>(b"foo LSHIFT 40").unwrap().0 != &b""[..]);
+        assert!(assignment::<u16>(b"65536 -> x").is_err());
     }
 
     #[test]
     fn evaluate_value() {
-        let circ = Circuit::new("123 -> x");
+        let circ = Circuit::<u16>::new("123 -> x");
         assert_eq!(circ.eval_value(&Value::Signal(111)), 111);
         assert_eq!(circ.eval_value(&Value::Wire("x")), 123);
     }
 
     #[test]
     fn evaluate_expression() {
-        let circ = Circuit::new("123 -> x");
+        let circ = Circuit::<u16>::new("123 -> x");
         assert_eq!(circ.eval_expression(&Expression::Not(Value::Wire("x"))), 65412);
         assert_eq!(circ.eval_expression(&Expression::And(Value::Wire("x"), Value::Signal(14))), 10);
         assert_eq!(circ.eval_expression(&Expression::Or(Value::Wire("x"), Value::Signal(14))), 127);
@@ -213,7 +596,7 @@ mod tests {
 
     #[test]
     fn evaluate() {
-        let circ = Circuit::new("123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nx LSHIFT 2 -> f\ny RSHIFT 2 -> g\nNOT x -> h\nNOT y -> i");
+        let circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nx LSHIFT 2 -> f\ny RSHIFT 2 -> g\nNOT x -> h\nNOT y -> i");
         assert_eq!(circ.eval("x"), 123);
         assert_eq!(circ.eval("y"), 456);
         assert_eq!(circ.eval("d"), 72);
@@ -224,11 +607,138 @@ mod tests {
         assert_eq!(circ.eval("i"), 65079);
     }
 
+    #[test]
+    fn evaluate_width() {
+        let circ = Circuit::<u32>::new("123 -> x\nNOT x -> h\nx LSHIFT 20 -> f");
+        assert_eq!(circ.eval("h"), 4294967172);
+        assert_eq!(circ.eval("f"), 128974848);
+        let circ = Circuit::<u64>::new("123 -> x\nNOT x -> h\nx LSHIFT 40 -> f");
+        assert_eq!(circ.eval("h"), 18446744073709551492);
+        assert_eq!(circ.eval("f"), 135239930216448);
+        let circ = Circuit::<u8>::new("123 -> x\nNOT x -> h\nx LSHIFT 2 -> f");
+        assert_eq!(circ.eval("h"), 132);
+        assert_eq!(circ.eval("f"), 236);
+    }
+
+    #[test]
+    fn displaying() {
+        assert_eq!(Expression::<u16>::And(Value::Wire("x"), Value::Signal(1)).to_string(), "x AND 1");
+        assert_eq!(Expression::<u16>::RShift(Value::Wire("x"), 2).to_string(), "x RSHIFT 2");
+        assert_eq!(Expression::<u16>::Not(Value::Wire("x")).to_string(), "NOT x");
+        assert_eq!(Expression::<u16>::Value(Value::Signal(123)).to_string(), "123");
+    }
+
+    #[test]
+    fn dependencies() {
+        let circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nd OR y -> e\nNOT x -> h\ne LSHIFT 1 -> a");
+        let mut inputs: Vec<&str> = circ.inputs("a").into_iter().collect();
+        inputs.sort();
+        assert_eq!(inputs, vec!["d", "e", "x", "y"]);
+        assert!(circ.inputs("x").is_empty());
+        let mut affected: Vec<&str> = circ.affected("x").into_iter().collect();
+        affected.sort();
+        assert_eq!(affected, vec!["a", "d", "e", "h"]);
+        assert!(circ.affected("a").is_empty());
+    }
+
+    #[test]
+    fn exporting_dot() {
+        let circ = Circuit::<u16>::new("123 -> x\nx AND 1 -> d\nNOT d -> a");
+        assert_eq!(circ.to_dot(), "digraph circuit {\n    \"a\" [label=\"NOT d -> a\"];\n    \"d\" [label=\"x AND 1 -> d\"];\n    \"x\" [label=\"123 -> x\"];\n    \"d\" -> \"a\" [label=\"d\"];\n    \"x\" -> \"d\" [label=\"x\"];\n}\n");
+    }
+
     #[test]
     fn overrriding() {
-        let mut circ = Circuit::new("123 -> x");
+        let mut circ = Circuit::<u16>::new("123 -> x");
         assert_eq!(circ.eval_expression(&Expression::Value(Value::Wire("x"))), 123);
         circ.set("x", 456);
         assert_eq!(circ.eval_expression(&Expression::Value(Value::Wire("x"))), 456);
     }
+
+    #[test]
+    fn incremental_overriding() {
+        let mut circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nNOT y -> i\nd OR e -> a");
+        assert_eq!(circ.eval("a"), 507);
+        assert_eq!(circ.eval("i"), 65079);
+        circ.set("d", 4);
+        assert!(circ.cache.borrow().contains_key("e"));
+        assert!(circ.cache.borrow().contains_key("i"));
+        assert!(!circ.cache.borrow().contains_key("a"));
+        assert_eq!(circ.eval("a"), 511);
+        circ.set("x", 0);
+        assert!(!circ.affected("x").contains("d"));
+        assert!(!circ.cache.borrow().contains_key("e"));
+        assert!(circ.cache.borrow().contains_key("i"));
+        assert_eq!(circ.eval("a"), 460);
+    }
+
+    #[test]
+    fn solving() {
+        let circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nNOT x -> h\nh OR 1 -> a");
+        assert_eq!(circ.solve("h", 65412, &["x"]), Ok(Some(vec![123])));
+        assert_eq!(circ.solve("a", 65413, &["x", "y"]), Ok(Some(vec![122, 456])));
+        assert_eq!(circ.solve("a", 65412, &["x"]), Ok(None));
+        assert_eq!(circ.solve("zz", 5, &["x"]), Err("unknown wire 'zz'".to_owned()));
+        assert_eq!(circ.solve("a", 5, &["nope"]), Err("unknown wire 'nope'".to_owned()));
+        assert_eq!(circ.solve("a", 65413, &["x", "nope"]), Err("unknown wire 'nope'".to_owned()));
+        assert_eq!(circ.eval("a"), 65413);
+        assert_eq!(circ.eval("x"), 123);
+        let mut circ = Circuit::<u8>::new("12 -> x\n34 -> y\nx AND y -> d\nd LSHIFT 1 -> e\ne OR x -> a");
+        let solution = circ.solve("a", 255, &["x", "y"]).unwrap().unwrap();
+        circ.set_all(&[("x", solution[0]), ("y", solution[1])], &[]);
+        assert_eq!(circ.eval("a"), 255);
+        assert_eq!(circ.solve("e", 1, &["x", "y"]), Ok(None));
+        let circ = Circuit::<u32>::new("1 -> x\nNOT x -> a");
+        assert_eq!(circ.solve("a", 5, &["x"]), Ok(Some(vec![!5])));
+        let circ = Circuit::<u64>::new("1 -> x\n2 -> y\nx LSHIFT 3 -> b\nb OR y -> c\nc RSHIFT 1 -> d\nd AND x -> a");
+        let solution = circ.solve("a", 0x0123456789abcdef, &["x", "y"]).unwrap().unwrap();
+        assert_eq!(((solution[0] << 3 | solution[1]) >> 1) & solution[0], 0x0123456789abcdef);
+        assert_eq!(circ.solve("b", 1, &["x", "y"]), Ok(None));
+    }
+
+    #[test]
+    fn simplifying() {
+        let circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nNOT x -> h\nd -> k\nk -> l\nl OR e -> a\nh AND 7 -> z");
+        assert_eq!(circ.simplify(&[], &["a"]).to_string(), "507 -> a");
+        assert_eq!(circ.simplify(&["x"], &["a"]).to_string(), "d OR e -> a\nx AND 456 -> d\nx OR 456 -> e\n123 -> x");
+        assert_eq!(circ.simplify(&["y"], &["a", "k"]).to_string(), "k OR e -> a\n123 OR y -> e\n123 AND y -> k\n456 -> y");
+        let circ = Circuit::<u16>::new("b -> c\nc -> d\nNOT d -> a\n5 -> b");
+        assert_eq!(circ.simplify(&["b"], &["a"]).to_string(), "NOT b -> a\n5 -> b");
+    }
+
+    #[test]
+    fn simplified_equivalence() {
+        let circ = Circuit::<u16>::new(include_str!("day07.txt"));
+        let simplified = circ.simplify(&["b"], &["a"]);
+        assert!(simplified.wires.len() < circ.wires.len());
+        let text = simplified.to_string();
+        let mut reparsed = Circuit::<u16>::new(&text);
+        let mut circ = circ;
+        assert_eq!(reparsed.eval("a"), circ.eval("a"));
+        circ.set("b", 1234);
+        reparsed.set("b", 1234);
+        assert_eq!(reparsed.eval("a"), circ.eval("a"));
+    }
+
+    #[test]
+    fn clocking() {
+        let mut circ = Circuit::<u16>::new("NOT q -> q");
+        circ.register("q", 0);
+        assert_eq!(circ.run(3, &["q"]), vec![vec![0], vec![65535], vec![0]]);
+        let mut circ = Circuit::<u8>::new("123 -> x\nx -> y\ny AND 15 -> z\nq LSHIFT 1 -> s\ns OR 1 -> q");
+        circ.register("y", 0);
+        circ.register("q", 0);
+        assert_eq!(circ.eval("z"), 0);
+        circ.step();
+        assert_eq!(circ.eval("z"), 11);
+        assert_eq!(circ.run(5, &["q", "s"]), vec![vec![1, 2], vec![3, 6], vec![7, 14], vec![15, 30], vec![31, 62]]);
+        assert_eq!(circ.simplify(&[], &["z", "q"]).to_string(), "s OR 1 -> q\nq LSHIFT 1 -> s\n123 -> y\ny AND 15 -> z");
+    }
+
+    #[test]
+    fn batch_overriding() {
+        let mut circ = Circuit::<u16>::new("123 -> x\n456 -> y\nx AND y -> d\nx OR y -> e\nNOT y -> i");
+        assert_eq!(circ.set_all(&[], &["d", "e", "i"]), vec![72, 507, 65079]);
+        assert_eq!(circ.set_all(&[("x", 1), ("y", 3)], &["d", "e", "i"]), vec![1, 3, 65532]);
+    }
 }
@@ -1,7 +1,11 @@
 #[macro_use]
 extern crate nom;
 
+use std::collections::HashMap;
 use std::fmt;
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
 use nom::eol;
 
 named!(line<Vec<bool> >,
@@ -22,6 +26,203 @@ named!(lights<Vec<Vec<bool> > >,
     )
 );
 
+/// Life-like rule, giving the numbers of lit neighbors that turn a light on
+/// (birth) and keep it on (survival)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    birth: [bool; 9],
+    survival: [bool; 9],
+}
+
+impl Default for Rule {
+    /// Conway's Game of Life, B3/S23
+    fn default() -> Rule {
+        Rule {
+            birth: [false, false, false, true, false, false, false, false, false],
+            survival: [false, false, true, true, false, false, false, false, false],
+        }
+    }
+}
+
+impl Rule {
+    /// Parses the neighbor counts of one half of a rule, following the given prefix
+    fn counts(part: &str, prefix: char) -> Result<[bool; 9], String> {
+        let mut chars = part.chars();
+        if chars.next().map(|c| c.to_ascii_uppercase()) != Some(prefix) {
+            return Err(format!("expected {} in front of '{}'", prefix, part));
+        }
+        let mut counts = [false; 9];
+        for c in chars {
+            match c.to_digit(10) {
+                Some(n) if n <= 8 && !counts[n as usize] => counts[n as usize] = true,
+                Some(n) if n <= 8 => return Err(format!("neighbor count {} repeated in '{}'", n, part)),
+                _ => return Err(format!("invalid neighbor count '{}' in '{}'", c, part)),
+            }
+        }
+        Ok(counts)
+    }
+
+    pub fn next(&self, light: bool, neighbors: usize) -> bool {
+        if light { self.survival[neighbors] } else { self.birth[neighbors] }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let parts: Vec<&str> = s.trim().split('/').collect();
+        if parts.len() != 2 {
+            return Err(format!("rule '{}' must be in B/S notation, like B3/S23", s.trim()));
+        }
+        Ok(Rule { birth: try!(Rule::counts(parts[0], 'B')), survival: try!(Rule::counts(parts[1], 'S')) })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = |counts: &[bool; 9]| -> String {
+            (0..9).filter(|&n| counts[n]).map(|n| n.to_string()).collect()
+        };
+        write!(f, "B{}/S{}", digits(&self.birth), digits(&self.survival))
+    }
+}
+
+/// How lights outside of the grid are treated when counting neighbors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    /// Lights outside are off
+    Dead,
+    /// Lights outside wrap around to the opposite edge
+    Toroidal,
+    /// Lights outside mirror the lights inside, as if reflected at the edge
+    Mirrored,
+}
+
+impl Boundary {
+    /// Index inside of 0..len that the given index refers to, if any
+    fn index(&self, n: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        match *self {
+            _ if n >= 0 && n < len => Some(n as usize),
+            Boundary::Dead => None,
+            Boundary::Toroidal => Some(((n % len + len) % len) as usize),
+            Boundary::Mirrored if n < 0 => Some((-n - 1).min(len - 1) as usize),
+            Boundary::Mirrored => Some((2 * len - n - 1).max(0) as usize),
+        }
+    }
+}
+
+/// Rule, boundary and pinned lights to animate a grid with. Pinned lights are
+/// forced on or off before and after every step, and animating a grid that
+/// doesn't contain all of them is an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    rule: Rule,
+    boundary: Boundary,
+    pinned: HashMap<(usize, usize), bool>,
+}
+
+impl Default for Animation {
+    fn default() -> Animation {
+        Animation::new(Rule::default())
+    }
+}
+
+impl Animation {
+    pub fn new(rule: Rule) -> Animation {
+        Animation { rule: rule, boundary: Boundary::Dead, pinned: HashMap::new() }
+    }
+
+    pub fn with_boundary(mut self, boundary: Boundary) -> Animation {
+        self.boundary = boundary;
+        self
+    }
+
+    pub fn pin(mut self, x: usize, y: usize, light: bool) -> Animation {
+        self.pinned.insert((x, y), light);
+        self
+    }
+
+    /// Pins the corners of a grid of the given size on
+    pub fn stuck_corners(self, width: usize, height: usize) -> Animation {
+        if width == 0 || height == 0 {
+            return self;
+        }
+        self.pin(0, 0, true).pin(width - 1, 0, true).pin(0, height - 1, true).pin(width - 1, height - 1, true)
+    }
+
+    /// Checks that all pinned lights are inside a grid of the given size
+    fn check(&self, width: usize, height: usize) -> Result<(), String> {
+        match self.pinned.keys().filter(|&&(x, y)| x >= width || y >= height).min() {
+            Some(&(x, y)) => Err(format!("pinned light {},{} is outside of the {}x{} grid", x, y, width, height)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Grids repeat after a transient number of steps, every period steps. A
+/// period of 1 is a still life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    pub transient: usize,
+    pub period: usize,
+}
+
+/// Applies the given step n times, but stops as soon as the state repeats and
+/// skips all remaining whole cycles. Repeats are found with Brent's algorithm,
+/// comparing against a single snapshot that is renewed after every power of
+/// two steps, so no more than two states are kept. Returns the period, if the
+/// state repeated.
+fn skip_cycles<T: Clone + PartialEq, F: FnMut(&mut T)>(state: &mut T, n: usize, mut step: F) -> Option<usize> {
+    let mut snapshot = state.clone();
+    let (mut power, mut since) = (1, 0);
+    for done in 1..n + 1 {
+        step(state);
+        since += 1;
+        if *state == snapshot {
+            for _ in 0..(n - done) % since {
+                step(state);
+            }
+            return Some(since);
+        }
+        if since == power {
+            snapshot = state.clone();
+            power *= 2;
+            since = 0;
+        }
+    }
+    None
+}
+
+/// Finds the cycle that the given state runs into, if its transient and period
+/// add up to no more than the given number of steps
+fn find_cycle<T: Clone + PartialEq, F: FnMut(&mut T)>(start: &T, max_steps: usize, mut step: F) -> Option<Cycle> {
+    // Brent's algorithm sees the repeat within three times the steps it takes
+    let period = match skip_cycles(&mut start.clone(), max_steps.saturating_add(1).saturating_mul(3), &mut step) {
+        Some(period) => period,
+        None => return None,
+    };
+    let (mut slow, mut fast) = (start.clone(), start.clone());
+    for _ in 0..period {
+        step(&mut fast);
+    }
+    let mut transient = 0;
+    while slow != fast && transient + period <= max_steps {
+        step(&mut slow);
+        step(&mut fast);
+        transient += 1;
+    }
+    if slow == fast && transient + period <= max_steps {
+        Some(Cycle { transient: transient, period: period })
+    } else {
+        None
+    }
+}
+
+/// Maximum length of a line in plain PGM images
+const PGM_LINE_LENGTH: usize = 70;
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Grid(Vec<Vec<bool> >);
 
@@ -47,7 +248,7 @@ impl fmt::Debug for Grid {
 }
 
 impl Grid {
-    fn count(&self) -> usize {
+    pub fn count(&self) -> usize {
         let mut count = 0;
         for y in 0..self.0.len() {
             for x in 0..self.0[y].len() {
@@ -60,57 +261,738 @@ impl Grid {
         count
     }
 
-    fn lit_neighbors(&self, y: usize, x: usize) -> usize {
-        fn lit(lights: &[Vec<bool>], y: isize, x: isize) -> bool {
-            y >= 0 && y < lights.len() as isize &&
-            x >= 0 && x < lights[y as usize].len() as isize &&
-            lights[y as usize][x as usize]
-        }
+    fn size(&self) -> (usize, usize) {
+        (self.0.first().map(|line| line.len()).unwrap_or(0), self.0.len())
+    }
+
+    fn lit_neighbors(&self, y: usize, x: usize, boundary: Boundary) -> usize {
+        let (width, height) = self.size();
         [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)].iter().map(|&(dy, dx)| {
-            lit(&self.0, y as isize + dy, x as isize + dx)
+            match (boundary.index(y as isize + dy, height), boundary.index(x as isize + dx, width)) {
+                (Some(y), Some(x)) => self.0[y][x],
+                _ => false,
+            }
         }).filter(|&l| l).count()
     }
 
-    fn animate(&self) -> Grid {
-        Grid((0..self.0.len()).map(|y| {
-            (0..self.0[y].len()).map(|x| {
-                match (self.0[y][x], self.lit_neighbors(y, x)) {
-                    (true, 2...3) => true,
-                    (true, _) => false,
-                    (false, 3) => true,
-                    (false, _) => false,
-                }
-            }).collect()
-        }).collect())
+    fn pinned(mut self, animation: &Animation) -> Grid {
+        for (&(x, y), &light) in &animation.pinned {
+            self.0[y][x] = light;
+        }
+        self
+    }
+
+    pub fn animate(&self) -> Grid {
+        self.animate_with(&Rule::default())
     }
 
-    fn animate_n(self, n: usize) -> Grid {
-        (0..n).fold(self, |g, _| g.animate())
+    pub fn animate_with(&self, rule: &Rule) -> Grid {
+        self.step(&Animation::new(*rule))
     }
 
-    fn xanimate(self) -> Grid {
+    pub fn animate_by(&self, animation: &Animation) -> Result<Grid, String> {
+        let (width, height) = self.size();
+        try!(animation.check(width, height));
+        Ok(self.step(animation))
+    }
+
+    fn step(&self, animation: &Animation) -> Grid {
+        let pinned;
+        let grid = if animation.pinned.is_empty() { self } else { pinned = self.clone().pinned(animation); &pinned };
+        Grid((0..grid.0.len()).map(|y| {
+            (0..grid.0[y].len()).map(|x| animation.rule.next(grid.0[y][x], grid.lit_neighbors(y, x, animation.boundary))).collect()
+        }).collect()).pinned(animation)
+    }
+
+    /// Animates n steps, skipping whole cycles as soon as the grid repeats
+    pub fn animate_n_by(self, n: usize, animation: &Animation) -> Result<Grid, String> {
+        let (width, height) = self.size();
+        try!(animation.check(width, height));
+        Ok(self.steps(n, animation))
+    }
+
+    fn steps(self, n: usize, animation: &Animation) -> Grid {
         let mut grid = self;
-        *grid.0.first_mut().unwrap().first_mut().unwrap() = true;
-        *grid.0.first_mut().unwrap().last_mut().unwrap() = true;
-        *grid.0.last_mut().unwrap().first_mut().unwrap() = true;
-        *grid.0.last_mut().unwrap().last_mut().unwrap() = true;
-        grid = grid.animate();
-        *grid.0.first_mut().unwrap().first_mut().unwrap() = true;
-        *grid.0.first_mut().unwrap().last_mut().unwrap() = true;
-        *grid.0.last_mut().unwrap().first_mut().unwrap() = true;
-        *grid.0.last_mut().unwrap().last_mut().unwrap() = true;
+        skip_cycles(&mut grid, n, |g| *g = g.step(animation));
         grid
     }
 
-    fn xanimate_n(self, n: usize) -> Grid {
-        (0..n).fold(self, |g, _| g.xanimate())
+    /// Animates until the grid repeats, for no more than the given number of steps
+    pub fn find_cycle(&self, animation: &Animation, max_steps: usize) -> Result<Option<Cycle>, String> {
+        let (width, height) = self.size();
+        try!(animation.check(width, height));
+        Ok(find_cycle(self, max_steps, |g| *g = g.step(animation)))
+    }
+
+    pub fn animate_n(self, n: usize) -> Grid {
+        self.animate_n_with(n, &Rule::default())
+    }
+
+    pub fn animate_n_with(self, n: usize, rule: &Rule) -> Grid {
+        self.steps(n, &Animation::new(*rule))
+    }
+
+    pub fn xanimate(self) -> Grid {
+        self.xanimate_with(&Rule::default())
+    }
+
+    pub fn xanimate_with(self, rule: &Rule) -> Grid {
+        let (width, height) = self.size();
+        self.step(&Animation::new(*rule).stuck_corners(width, height))
+    }
+
+    pub fn xanimate_n(self, n: usize) -> Grid {
+        self.xanimate_n_with(n, &Rule::default())
+    }
+
+    pub fn xanimate_n_with(self, n: usize, rule: &Rule) -> Grid {
+        let (width, height) = self.size();
+        self.steps(n, &Animation::new(*rule).stuck_corners(width, height))
+    }
+
+    /// Grid of the given size with all lights off
+    pub fn new(width: usize, height: usize) -> Grid {
+        Grid(vec![vec![false; width]; height])
+    }
+
+    /// Grid of the given size, with the given lines padded by lights that are off
+    fn padded(lines: Vec<Vec<bool>>, width: usize, height: usize) -> Grid {
+        let mut grid = Grid::new(width, height);
+        for (y, line) in lines.into_iter().enumerate() {
+            for (x, light) in line.into_iter().enumerate() {
+                grid.0[y][x] = light;
+            }
+        }
+        grid
+    }
+
+    /// Parses a pattern in run length encoded format, returning the pattern
+    /// and the rule given in its header (B3/S23 if there's none)
+    pub fn from_rle(text: &str) -> Result<(Grid, Rule), String> {
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let header = try!(lines.next().ok_or("missing RLE header".to_owned()));
+        let (mut width, mut height, mut rule) = (None, None, Rule::default());
+        for field in header.split(',') {
+            let parts: Vec<&str> = field.split('=').map(|part| part.trim()).collect();
+            match (parts.len(), parts[0]) {
+                (2, "x") => width = parts[1].parse().ok(),
+                (2, "y") => height = parts[1].parse().ok(),
+                (2, "rule") => rule = try!(parts[1].parse()),
+                _ => return Err(format!("invalid RLE header '{}'", header)),
+            }
+        }
+        let (width, height): (usize, usize) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(format!("invalid RLE header '{}'", header)),
+        };
+        let too_large = || format!("RLE pattern is larger than {}x{}", width, height);
+        let mut rows = vec![vec![]];
+        let mut count: Option<usize> = None;
+        for c in lines.flat_map(|line| line.chars()).filter(|c| !c.is_whitespace()) {
+            if let Some(digit) = c.to_digit(10) {
+                let n = count.unwrap_or(0).checked_mul(10).and_then(|n| n.checked_add(digit as usize));
+                count = Some(try!(n.ok_or_else(&too_large)));
+                continue;
+            }
+            // Runs are checked against the header before they are expanded
+            let n = count.take().unwrap_or(1);
+            match c {
+                'b' | 'o' => {
+                    let row = rows.last_mut().unwrap();
+                    if n > width - row.len() {
+                        return Err(too_large());
+                    }
+                    row.extend(vec![c == 'o'; n]);
+                }
+                '$' => {
+                    if n > height.saturating_sub(rows.len()) {
+                        return Err(too_large());
+                    }
+                    rows.extend(vec![vec![]; n]);
+                }
+                '!' => break,
+                _ => return Err(format!("invalid character '{}' in RLE pattern", c)),
+            }
+        }
+        if rows.len() > height {
+            return Err(too_large());
+        }
+        Ok((Grid::padded(rows, width, height), rule))
+    }
+
+    /// Encodes the grid in run length encoded format, with the given rule in the header
+    pub fn to_rle(&self, rule: &Rule) -> String {
+        fn push(runs: &mut Vec<(usize, char)>, n: usize, c: char) {
+            match runs.last_mut() {
+                Some(&mut (ref mut m, d)) if d == c => { *m += n; return },
+                _ => (),
+            }
+            runs.push((n, c));
+        }
+        let (width, height) = self.size();
+        let mut runs = Vec::new();
+        for (y, line) in self.0.iter().enumerate() {
+            if y > 0 {
+                push(&mut runs, 1, '$');
+            }
+            let last = line.iter().rposition(|&light| light).map(|x| x + 1).unwrap_or(0);
+            for &light in &line[..last] {
+                push(&mut runs, 1, if light { 'o' } else { 'b' });
+            }
+        }
+        while runs.last().map(|&(_, c)| c) == Some('$') {
+            runs.pop();
+        }
+        runs.push((1, '!'));
+        let mut rle = format!("x = {}, y = {}, rule = {}\n", width, height, rule);
+        let mut line = String::new();
+        for (n, c) in runs {
+            let item = if n == 1 { c.to_string() } else { format!("{}{}", n, c) };
+            if line.len() + item.len() > 70 {
+                rle.push_str(&line);
+                rle.push('\n');
+                line.clear();
+            }
+            line.push_str(&item);
+        }
+        rle.push_str(&line);
+        rle.push('\n');
+        rle
+    }
+
+    /// Parses a pattern in plaintext (.cells) format, where `O` is a lit light
+    /// and lines starting with `!` are comments
+    pub fn from_cells(text: &str) -> Result<Grid, String> {
+        let mut rows = Vec::new();
+        for line in text.lines().filter(|line| !line.starts_with('!')) {
+            rows.push(try!(line.trim().chars().map(|c| match c {
+                'O' => Ok(true),
+                '.' => Ok(false),
+                _ => Err(format!("invalid character '{}' in plaintext pattern", c)),
+            }).collect::<Result<Vec<bool>, String>>()));
+        }
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let height = rows.len();
+        Ok(Grid::padded(rows, width, height))
+    }
+
+    /// Encodes the grid in plaintext (.cells) format
+    pub fn to_cells(&self) -> String {
+        self.0.iter().map(|line| {
+            let mut line: String = line.iter().map(|&light| if light { 'O' } else { '.' }).collect();
+            line.push('\n');
+            line
+        }).collect()
+    }
+
+    /// Copies a pattern into the grid, with its top left corner at the given position
+    pub fn place(&mut self, pattern: &Grid, x: usize, y: usize) -> Result<(), String> {
+        let (width, height) = self.size();
+        let (w, h) = pattern.size();
+        if x + w > width || y + h > height {
+            return Err(format!("{}x{} pattern at {},{} doesn't fit into {}x{} grid", w, h, x, y, width, height));
+        }
+        for (dy, line) in pattern.0.iter().enumerate() {
+            self.0[y + dy][x..x + w].copy_from_slice(line);
+        }
+        Ok(())
+    }
+
+    /// Writes the grid as plain PGM image, with lit lights in white
+    pub fn write_pgm<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = self.size();
+        try!(write!(out, "P2\n{} {}\n1\n", width, height));
+        for line in &self.0 {
+            let mut len = 0;
+            for &light in line {
+                if len + 2 > PGM_LINE_LENGTH {
+                    try!(out.write_all(b"\n"));
+                    len = 0;
+                } else if len > 0 {
+                    try!(out.write_all(b" "));
+                    len += 1;
+                }
+                try!(out.write_all(if light { b"1" } else { b"0" }));
+                len += 1;
+            }
+            try!(out.write_all(b"\n"));
+        }
+        Ok(())
+    }
+
+    fn write_frames<F: Fn(Grid) -> Grid>(self, n: usize, prefix: &str, animate: F) -> io::Result<Grid> {
+        let mut grid = self;
+        for i in 0..n + 1 {
+            if i > 0 {
+                grid = animate(grid);
+            }
+            let mut file = try!(File::create(format!("{}{:04}.pgm", prefix, i)));
+            try!(grid.write_pgm(&mut file));
+        }
+        Ok(grid)
+    }
+
+    /// Like animate_n, but writes every frame (including the initial one) as
+    /// numbered PGM image, named by the given prefix and the frame number
+    pub fn animate_n_frames(self, n: usize, prefix: &str) -> io::Result<Grid> {
+        self.write_frames(n, prefix, |g| g.animate())
+    }
+
+    /// Like xanimate_n, but writes every frame as numbered PGM image
+    pub fn xanimate_n_frames(self, n: usize, prefix: &str) -> io::Result<Grid> {
+        self.write_frames(n, prefix, |g| g.xanimate())
+    }
+}
+
+/// Grid with lights packed into 64 bit words, row by row. Bits beyond the
+/// width of a row are always zero. Animating updates the grid in place,
+/// alternating between two buffers.
+#[derive(Clone)]
+pub struct BitGrid {
+    width: usize,
+    height: usize,
+    words: usize,
+    cells: Vec<u64>,
+    next: Vec<u64>,
+}
+
+/// Counts the number of set bits at every position of up to 15 words in
+/// parallel, keeping the count as four bit planes
+#[derive(Default)]
+struct BitCounter([u64; 4]);
+
+impl BitCounter {
+    fn add(&mut self, bits: u64) {
+        let mut carry = bits;
+        for plane in self.0.iter_mut() {
+            let sum = *plane ^ carry;
+            carry &= *plane;
+            *plane = sum;
+        }
+    }
+
+    /// Positions at which the count equals n
+    fn equals(&self, n: usize) -> u64 {
+        self.0.iter().enumerate().fold(!0, |acc, (i, &plane)| {
+            acc & if n & (1 << i) != 0 { plane } else { !plane }
+        })
+    }
+}
+
+impl<'a> From<&'a Grid> for BitGrid {
+    fn from(grid: &Grid) -> BitGrid {
+        let height = grid.0.len();
+        let width = grid.0.first().map(|line| line.len()).unwrap_or(0);
+        let words = (width + 63) / 64;
+        let mut cells = vec![0; words * height];
+        for (y, line) in grid.0.iter().enumerate() {
+            for (x, &light) in line.iter().enumerate() {
+                if light {
+                    cells[y * words + x / 64] |= 1 << (x % 64);
+                }
+            }
+        }
+        BitGrid { width: width, height: height, words: words, next: cells.clone(), cells: cells }
+    }
+}
+
+impl<'a> From<&'a BitGrid> for Grid {
+    fn from(grid: &BitGrid) -> Grid {
+        Grid((0..grid.height).map(|y| (0..grid.width).map(|x| grid.get(x, y)).collect()).collect())
+    }
+}
+
+impl PartialEq for BitGrid {
+    fn eq(&self, other: &BitGrid) -> bool {
+        self.width == other.width && self.height == other.height && self.cells == other.cells
+    }
+}
+
+impl Eq for BitGrid {}
+
+impl fmt::Debug for BitGrid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Grid::from(self).fmt(f)
+    }
+}
+
+impl BitGrid {
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.cells[y * self.words + x / 64] & (1 << (x % 64)) != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, light: bool) {
+        assert!(x < self.width && y < self.height, "light {},{} is outside of the {}x{} grid", x, y, self.width, self.height);
+        let bit = 1 << (x % 64);
+        let word = &mut self.cells[y * self.words + x / 64];
+        if light { *word |= bit } else { *word &= !bit }
+    }
+
+    pub fn count(&self) -> usize {
+        self.cells.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Word i of row y, where rows outside of the grid and the lights right
+    /// next to the left and right edge follow the boundary. The light right of
+    /// the right edge is the bit after the last light of the row, the light
+    /// left of the left edge is the top bit of word -1.
+    fn word(&self, y: isize, i: isize, boundary: Boundary) -> u64 {
+        let y = match boundary.index(y, self.height) {
+            Some(y) => y,
+            None => return 0,
+        };
+        let outside = |x: isize| match boundary.index(x, self.width) {
+            Some(x) => self.get(x, y) as u64,
+            None => 0,
+        };
+        let words = self.words as isize;
+        if i == -1 {
+            outside(-1) << 63
+        } else if i == words - 1 && self.width % 64 != 0 {
+            self.cells[y * self.words + i as usize] | outside(self.width as isize) << (self.width % 64)
+        } else if i < words {
+            self.cells[y * self.words + i as usize]
+        } else if self.width % 64 == 0 {
+            outside(self.width as isize)
+        } else {
+            0
+        }
+    }
+
+    fn pin(&mut self, animation: &Animation) {
+        for (&(x, y), &light) in &animation.pinned {
+            self.set(x, y, light);
+        }
+    }
+
+    pub fn animate(&mut self) {
+        self.animate_with(&Rule::default())
+    }
+
+    pub fn animate_with(&mut self, rule: &Rule) {
+        self.step(&Animation::new(*rule))
+    }
+
+    pub fn animate_by(&mut self, animation: &Animation) -> Result<(), String> {
+        try!(animation.check(self.width, self.height));
+        self.step(animation);
+        Ok(())
+    }
+
+    fn step(&mut self, animation: &Animation) {
+        self.pin(animation);
+        let (rule, boundary) = (&animation.rule, animation.boundary);
+        let last = if self.width % 64 == 0 { !0 } else { (1 << (self.width % 64)) - 1 };
+        for y in 0..self.height as isize {
+            for i in 0..self.words as isize {
+                let mut neighbors = BitCounter::default();
+                for dy in -1..2 {
+                    let west = self.word(y + dy, i - 1, boundary);
+                    let word = self.word(y + dy, i, boundary);
+                    let east = self.word(y + dy, i + 1, boundary);
+                    neighbors.add(word << 1 | west >> 63);
+                    neighbors.add(word >> 1 | east << 63);
+                    if dy != 0 {
+                        neighbors.add(word);
+                    }
+                }
+                let alive = self.cells[y as usize * self.words + i as usize];
+                let next = (0..9).fold(0, |next, n| {
+                    let born = if rule.birth[n] { !alive } else { 0 };
+                    let survived = if rule.survival[n] { alive } else { 0 };
+                    next | neighbors.equals(n) & (born | survived)
+                });
+                self.next[y as usize * self.words + i as usize] = if i as usize == self.words - 1 { next & last } else { next };
+            }
+        }
+        ::std::mem::swap(&mut self.cells, &mut self.next);
+        self.pin(animation);
+    }
+
+    pub fn animate_n(&mut self, n: usize) {
+        self.animate_n_with(n, &Rule::default())
+    }
+
+    pub fn animate_n_with(&mut self, n: usize, rule: &Rule) {
+        self.steps(n, &Animation::new(*rule))
+    }
+
+    /// Animates n steps, skipping whole cycles as soon as the grid repeats
+    pub fn animate_n_by(&mut self, n: usize, animation: &Animation) -> Result<(), String> {
+        try!(animation.check(self.width, self.height));
+        self.steps(n, animation);
+        Ok(())
+    }
+
+    fn steps(&mut self, n: usize, animation: &Animation) {
+        skip_cycles(self, n, |g| g.step(animation));
+    }
+
+    /// Animates until the grid repeats, for no more than the given number of steps
+    pub fn find_cycle(&self, animation: &Animation, max_steps: usize) -> Result<Option<Cycle>, String> {
+        try!(animation.check(self.width, self.height));
+        Ok(find_cycle(self, max_steps, |g| g.step(animation)))
+    }
+
+    pub fn xanimate(&mut self) {
+        self.xanimate_with(&Rule::default())
+    }
+
+    pub fn xanimate_with(&mut self, rule: &Rule) {
+        let animation = Animation::new(*rule).stuck_corners(self.width, self.height);
+        self.step(&animation)
+    }
+
+    pub fn xanimate_n(&mut self, n: usize) {
+        self.xanimate_n_with(n, &Rule::default())
+    }
+
+    pub fn xanimate_n_with(&mut self, n: usize, rule: &Rule) {
+        let animation = Animation::new(*rule).stuck_corners(self.width, self.height);
+        self.steps(n, &animation)
+    }
+}
+
+/// State of a cell in Hashlife. Walls and stuck lights never change, walls
+/// are used for lights pinned off and everything outside of the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Cell {
+    Off,
+    On,
+    Wall,
+    Stuck,
+}
+
+impl Cell {
+    fn lit(&self) -> bool {
+        *self == Cell::On || *self == Cell::Stuck
+    }
+}
+
+/// Quadtree node, either a single cell or four quadrants (nw, ne, sw, se)
+/// of half the size, given by their node ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    Leaf(Cell),
+    Branch([usize; 4]),
+}
+
+/// Grid stored as a quadtree with shared nodes, that is animated by
+/// memoizing the future of every node (Hashlife). The grid is in the top
+/// left corner of the root node.
+pub struct Hashlife {
+    rule: Rule,
+    width: usize,
+    height: usize,
+    /// Every node with its level (size 2^level) and number of lit lights
+    nodes: Vec<(Node, usize, usize)>,
+    ids: HashMap<Node, usize>,
+    results: HashMap<(usize, usize), usize>,
+    walls: Vec<usize>,
+    root: usize,
+}
+
+impl Hashlife {
+    /// Converts a grid, which must have a dead boundary
+    pub fn new(grid: &Grid, animation: &Animation) -> Result<Hashlife, String> {
+        if animation.boundary != Boundary::Dead {
+            return Err(format!("{:?} boundary isn't supported by Hashlife", animation.boundary));
+        }
+        let (width, height) = grid.size();
+        try!(animation.check(width, height));
+        let mut life = Hashlife {
+            rule: animation.rule,
+            width: width,
+            height: height,
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            results: HashMap::new(),
+            walls: Vec::new(),
+            root: 0,
+        };
+        let mut level = 1;
+        while 1 << level < width.max(height) {
+            level += 1;
+        }
+        life.root = life.build(grid, animation, level, 0, 0);
+        Ok(life)
+    }
+
+    fn build(&mut self, grid: &Grid, animation: &Animation, level: usize, x: usize, y: usize) -> usize {
+        if x >= self.width || y >= self.height {
+            return self.wall(level);
+        }
+        if level == 0 {
+            let cell = match (animation.pinned.get(&(x, y)), grid.0[y][x]) {
+                (Some(&true), _) => Cell::Stuck,
+                (Some(&false), _) => Cell::Wall,
+                (None, true) => Cell::On,
+                (None, false) => Cell::Off,
+            };
+            return self.node(Node::Leaf(cell));
+        }
+        let half = 1 << (level - 1);
+        let nw = self.build(grid, animation, level - 1, x, y);
+        let ne = self.build(grid, animation, level - 1, x + half, y);
+        let sw = self.build(grid, animation, level - 1, x, y + half);
+        let se = self.build(grid, animation, level - 1, x + half, y + half);
+        self.node(Node::Branch([nw, ne, sw, se]))
+    }
+
+    /// Id of the given node, adding it if it's new
+    fn node(&mut self, node: Node) -> usize {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        let (level, population) = match node {
+            Node::Leaf(cell) => (0, cell.lit() as usize),
+            Node::Branch(quadrants) => (self.level(quadrants[0]) + 1, quadrants.iter().map(|&q| self.nodes[q].2).sum()),
+        };
+        self.nodes.push((node, level, population));
+        self.ids.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn join(&mut self, nw: usize, ne: usize, sw: usize, se: usize) -> usize {
+        self.node(Node::Branch([nw, ne, sw, se]))
+    }
+
+    fn level(&self, id: usize) -> usize {
+        self.nodes[id].1
+    }
+
+    fn quadrants(&self, id: usize) -> [usize; 4] {
+        match self.nodes[id].0 {
+            Node::Branch(quadrants) => quadrants,
+            Node::Leaf(_) => panic!("leaf has no quadrants"),
+        }
+    }
+
+    /// Node of the given level filled with walls
+    fn wall(&mut self, level: usize) -> usize {
+        while self.walls.len() <= level {
+            let wall = match self.walls.last() {
+                Some(&w) => self.join(w, w, w, w),
+                None => self.node(Node::Leaf(Cell::Wall)),
+            };
+            self.walls.push(wall);
+        }
+        self.walls[level]
+    }
+
+    fn cell(&self, id: usize, x: usize, y: usize) -> Cell {
+        match self.nodes[id].0 {
+            Node::Leaf(cell) => cell,
+            Node::Branch(quadrants) => {
+                let half = 1 << (self.level(id) - 1);
+                let q = if y < half { 0 } else { 2 } + if x < half { 0 } else { 1 };
+                self.cell(quadrants[q], x % half, y % half)
+            },
+        }
+    }
+
+    /// Center 2x2 cells of a 4x4 node after one step
+    fn step_leaves(&mut self, id: usize) -> usize {
+        let cells: Vec<Vec<Cell>> = (0..4).map(|y| (0..4).map(|x| self.cell(id, x, y)).collect()).collect();
+        let rule = self.rule;
+        let next = |x: usize, y: usize| {
+            let neighbors = (y - 1..y + 2).flat_map(|ny| (x - 1..x + 2).map(move |nx| (nx, ny)))
+                .filter(|&(nx, ny)| (nx, ny) != (x, y) && cells[ny][nx].lit()).count();
+            match cells[y][x] {
+                Cell::Off | Cell::On if rule.next(cells[y][x] == Cell::On, neighbors) => Cell::On,
+                Cell::Off | Cell::On => Cell::Off,
+                cell => cell,
+            }
+        };
+        let (nw, ne, sw, se) = (next(1, 1), next(2, 1), next(1, 2), next(2, 2));
+        let (nw, ne, sw, se) = (self.node(Node::Leaf(nw)), self.node(Node::Leaf(ne)), self.node(Node::Leaf(sw)), self.node(Node::Leaf(se)));
+        self.join(nw, ne, sw, se)
+    }
+
+    /// Center node of half the size after 2^j steps, where j is limited to the
+    /// level of the node minus 2
+    fn successor(&mut self, id: usize, j: usize) -> usize {
+        let level = self.level(id);
+        let j = j.min(level - 2);
+        if let Some(&result) = self.results.get(&(id, j)) {
+            return result;
+        }
+        let result = if level == 2 {
+            self.step_leaves(id)
+        } else {
+            let [nw, ne, sw, se] = self.quadrants(id);
+            let ([_, nwne, nwsw, nwse], [nenw, _, nesw, nese]) = (self.quadrants(nw), self.quadrants(ne));
+            let ([swnw, swne, _, swse], [senw, sene, sesw, _]) = (self.quadrants(sw), self.quadrants(se));
+            // Nine overlapping nodes of half the size, row by row
+            let n = self.join(nwne, nenw, nwse, nesw);
+            let w = self.join(nwsw, nwse, swnw, swne);
+            let c = self.join(nwse, nesw, swne, senw);
+            let e = self.join(nesw, nese, senw, sene);
+            let s = self.join(swne, senw, swse, sesw);
+            let parts = [nw, n, ne, w, c, e, sw, s, se];
+            // Nodes of half the size are advanced by at most half of the steps,
+            // so unless that's enough, their centers are advanced again
+            let mut centers = [0; 9];
+            for (i, &part) in parts.iter().enumerate() {
+                centers[i] = self.successor(part, j);
+            }
+            let corners = [[0, 1, 3, 4], [1, 2, 4, 5], [3, 4, 6, 7], [4, 5, 7, 8]];
+            let mut quadrants = [0; 4];
+            for (i, corner) in corners.iter().enumerate() {
+                let [a, b, c, d] = *corner;
+                quadrants[i] = if j < level - 2 {
+                    let (a, b, c, d) = (self.quadrants(centers[a])[3], self.quadrants(centers[b])[2], self.quadrants(centers[c])[1], self.quadrants(centers[d])[0]);
+                    self.join(a, b, c, d)
+                } else {
+                    let quadrant = self.join(centers[a], centers[b], centers[c], centers[d]);
+                    self.successor(quadrant, j)
+                };
+            }
+            self.join(quadrants[0], quadrants[1], quadrants[2], quadrants[3])
+        };
+        self.results.insert((id, j), result);
+        result
+    }
+
+    /// Animates n steps
+    pub fn animate_n(&mut self, n: usize) {
+        for j in 0..64 {
+            if n >> j & 1 == 0 {
+                continue;
+            }
+            while self.level(self.root) < j + 1 {
+                let wall = self.wall(self.level(self.root));
+                self.root = self.join(self.root, wall, wall, wall);
+            }
+            // Center the root in a node of twice the size, whose center is the root after 2^j steps
+            let wall = self.wall(self.level(self.root) - 1);
+            let [nw, ne, sw, se] = self.quadrants(self.root);
+            let (nw, ne) = (self.join(wall, wall, wall, nw), self.join(wall, wall, ne, wall));
+            let (sw, se) = (self.join(wall, sw, wall, wall), self.join(se, wall, wall, wall));
+            let expanded = self.join(nw, ne, sw, se);
+            self.root = self.successor(expanded, j);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.nodes[self.root].2
+    }
+}
+
+impl<'a> From<&'a Hashlife> for Grid {
+    fn from(life: &Hashlife) -> Grid {
+        Grid((0..life.height).map(|y| (0..life.width).map(|x| life.cell(life.root, x, y).lit()).collect()).collect())
     }
 }
 
 fn main() {
-    let grid = Grid::from(include_str!("day18.txt"));
-    println!("Lit lights after 100 animation steps: {}", grid.clone().animate_n(100).count());
-    println!("Lit lights after 100 animation steps, with broken corner lights: {}", grid.xanimate_n(100).count());
+    let grid = BitGrid::from(&Grid::from(include_str!("day18.txt")));
+    let mut animated = grid.clone();
+    animated.animate_n(100);
+    println!("Lit lights after 100 animation steps: {}", animated.count());
+    let mut animated = grid;
+    animated.xanimate_n(100);
+    println!("Lit lights after 100 animation steps, with broken corner lights: {}", animated.count());
 }
 
 #[cfg(test)]
@@ -149,6 +1031,40 @@ mod tests {
         assert_eq!(grid.count(), 4);
     }
 
+    #[test]
+    fn exporting_pgm() {
+        let grid = Grid::from(".#.\n##.");
+        let mut pgm = Vec::new();
+        grid.write_pgm(&mut pgm).unwrap();
+        assert_eq!(String::from_utf8(pgm).unwrap(), "P2\n3 2\n1\n0 1 0\n1 1 0\n");
+        let grid = Grid::from(&format!("{}.#", "#".repeat(40))[..]);
+        let mut pgm = Vec::new();
+        grid.write_pgm(&mut pgm).unwrap();
+        let pgm = String::from_utf8(pgm).unwrap();
+        assert_eq!(pgm, format!("P2\n42 1\n1\n{}1\n1 1 1 1 1 0 1\n", "1 ".repeat(34)));
+        assert!(pgm.lines().all(|line| line.len() <= 70));
+    }
+
+    #[test]
+    fn exporting_frames() {
+        use std::{env, fs, process};
+        use std::io::Read;
+        let dir = env::temp_dir().join(format!("day18-frames-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("frame").to_string_lossy().into_owned();
+        let grid = Grid::from(".#.#.#\n...##.\n#....#\n..#...\n#.#..#\n####..");
+        let grid = grid.animate_n_frames(4, &prefix).unwrap();
+        assert_eq!(grid, Grid::from("......\n......\n..##..\n..##..\n......\n......"));
+        let mut pgm = String::new();
+        File::open(format!("{}0004.pgm", prefix)).unwrap().read_to_string(&mut pgm).unwrap();
+        assert_eq!(pgm, "P2\n6 6\n1\n0 0 0 0 0 0\n0 0 0 0 0 0\n0 0 1 1 0 0\n0 0 1 1 0 0\n0 0 0 0 0 0\n0 0 0 0 0 0\n");
+        let grid = Grid::from("##.#.#\n...##.\n#....#\n..#...\n#.#..#\n####.#");
+        let grid = grid.xanimate_n_frames(5, &prefix).unwrap();
+        assert_eq!(grid.count(), 17);
+        assert!(File::open(format!("{}0005.pgm", prefix)).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn xanimating() {
         let grid = Grid::from("##.#.#\n...##.\n#....#\n..#...\n#.#..#\n####.#");
@@ -177,4 +1093,250 @@ mod tests {
         assert_eq!(grid, Grid::from("##.###\n.##..#\n.##...\n.##...\n#.#...\n##...#"));
         assert_eq!(grid.count(), 17);
     }
+
+    #[test]
+    fn bit_packing() {
+        let grid = Grid::from(".#.#.#\n...##.\n#....#\n..#...\n#.#..#\n####..");
+        let mut bits = BitGrid::from(&grid);
+        assert_eq!(Grid::from(&bits), grid);
+        assert_eq!(bits.count(), 15);
+        bits.animate_n(4);
+        assert_eq!(Grid::from(&bits), grid.animate_n(4));
+        let grid = Grid::from("##.#.#\n...##.\n#....#\n..#...\n#.#..#\n####.#");
+        let mut bits = BitGrid::from(&grid);
+        bits.xanimate_n(5);
+        assert_eq!(Grid::from(&bits), grid.xanimate_n(5));
+    }
+
+    #[test]
+    fn bit_packing_wide() {
+        let grid = Grid::from(include_str!("day18.txt"));
+        let mut bits = BitGrid::from(&grid);
+        bits.animate_n(10);
+        assert_eq!(Grid::from(&bits), grid.clone().animate_n(10));
+        let mut bits = BitGrid::from(&grid);
+        bits.xanimate_n(10);
+        assert_eq!(Grid::from(&bits), grid.xanimate_n(10));
+        let grid = Grid((0..3).map(|y| (0..130).map(|x| (x * 7 + y * 3) % 5 < 2).collect()).collect());
+        let mut bits = BitGrid::from(&grid);
+        bits.animate_n(20);
+        assert_eq!(Grid::from(&bits), grid.animate_n(20));
+    }
+
+    #[test]
+    fn parsing_rules() {
+        let rule: Rule = "B3/S23".parse().unwrap();
+        assert_eq!(rule, Rule::default());
+        assert_eq!("b36/s23".parse::<Rule>().unwrap().to_string(), "B36/S23");
+        assert_eq!("B2/S".parse::<Rule>().unwrap().to_string(), "B2/S");
+        assert_eq!("B3".parse::<Rule>(), Err("rule 'B3' must be in B/S notation, like B3/S23".to_string()));
+        assert_eq!("S23/B3".parse::<Rule>(), Err("expected B in front of 'S23'".to_string()));
+        assert_eq!("B39/S23".parse::<Rule>(), Err("invalid neighbor count '9' in 'B39'".to_string()));
+        assert_eq!("B3/S232".parse::<Rule>(), Err("neighbor count 2 repeated in 'S232'".to_string()));
+    }
+
+    #[test]
+    fn animating_rules() {
+        let seeds: Rule = "B2/S".parse().unwrap();
+        let grid = Grid::from("......\n..##..\n......\n......");
+        let grid = grid.animate_with(&seeds);
+        assert_eq!(grid, Grid::from("..##..\n......\n..##..\n......"));
+        let highlife: Rule = "B36/S23".parse().unwrap();
+        let grid = Grid::from(include_str!("day18.txt"));
+        let mut bits = BitGrid::from(&grid);
+        bits.animate_n_with(5, &highlife);
+        assert_eq!(Grid::from(&bits), grid.clone().animate_n_with(5, &highlife));
+        let mut bits = BitGrid::from(&grid);
+        bits.xanimate_n_with(5, &seeds);
+        assert_eq!(Grid::from(&bits), grid.xanimate_n_with(5, &seeds));
+    }
+
+    #[test]
+    fn boundaries() {
+        assert_eq!(Boundary::Dead.index(-1, 6), None);
+        assert_eq!(Boundary::Toroidal.index(-1, 6), Some(5));
+        assert_eq!(Boundary::Toroidal.index(6, 6), Some(0));
+        assert_eq!(Boundary::Mirrored.index(-1, 6), Some(0));
+        assert_eq!(Boundary::Mirrored.index(6, 6), Some(5));
+        // A glider on a torus is back where it started after 4 steps per cell
+        let glider = Grid::from(".#....\n..#...\n###...\n......\n......\n......");
+        let torus = Animation::default().with_boundary(Boundary::Toroidal);
+        assert_eq!(glider.clone().animate_n_by(24, &torus).unwrap(), glider);
+        // A blinker at the edge of a mirrored grid sees its own reflection
+        let grid = Grid::from("#....\n#....\n#....\n.....");
+        assert_eq!(grid.animate(), Grid::from(".....\n##...\n.....\n....."));
+        let mirrored = Animation::default().with_boundary(Boundary::Mirrored);
+        assert_eq!(grid.animate_by(&mirrored).unwrap(), Grid::from(".#...\n.#...\n#....\n....."));
+    }
+
+    #[test]
+    fn pinning() {
+        let grid = Grid::from("......\n..##..\n..##..\n......");
+        let animation = Animation::default().pin(2, 1, false).pin(5, 3, true);
+        assert_eq!(grid.animate_by(&animation).unwrap(), Grid::from("......\n...#..\n..###.\n.....#"));
+        let error = Some("pinned light 0,4 is outside of the 6x4 grid".to_owned());
+        let animation = Animation::default().pin(6, 0, true).pin(0, 4, true);
+        assert_eq!(grid.animate_by(&animation).err(), error);
+        assert_eq!(grid.clone().animate_n_by(3, &animation).err(), error);
+        assert_eq!(grid.find_cycle(&animation, 10).err(), error);
+        let mut bits = BitGrid::from(&grid);
+        assert_eq!(bits.animate_by(&animation).err(), error);
+        assert_eq!(bits.animate_n_by(3, &animation).err(), error);
+        assert_eq!(bits.find_cycle(&animation, 10).err(), error);
+        assert_eq!(bits, BitGrid::from(&grid));
+        assert_eq!(Hashlife::new(&grid, &animation).err(), error);
+        assert_eq!(Animation::default().stuck_corners(0, 4), Animation::default());
+        assert_eq!(Grid::new(0, 0).xanimate(), Grid::new(0, 0));
+    }
+
+    #[test]
+    fn bit_packing_boundaries() {
+        let grid = Grid::from(include_str!("day18.txt"));
+        for &boundary in &[Boundary::Dead, Boundary::Toroidal, Boundary::Mirrored] {
+            let animation = Animation::new("B36/S23".parse().unwrap()).with_boundary(boundary).pin(3, 5, true).pin(99, 0, false);
+            let mut bits = BitGrid::from(&grid);
+            bits.animate_n_by(5, &animation).unwrap();
+            assert_eq!(Grid::from(&bits), grid.clone().animate_n_by(5, &animation).unwrap());
+            let grid = Grid((0..7).map(|y| (0..128).map(|x| (x * 7 + y * 3) % 5 < 2).collect()).collect());
+            let mut bits = BitGrid::from(&grid);
+            bits.animate_n_by(5, &animation).unwrap();
+            assert_eq!(Grid::from(&bits), grid.animate_n_by(5, &animation).unwrap());
+        }
+    }
+
+    #[test]
+    fn detecting_cycles() {
+        let grid = Grid::from(".#.#.#\n...##.\n#....#\n..#...\n#.#..#\n####..");
+        assert_eq!(grid.find_cycle(&Animation::default(), 100), Ok(Some(Cycle { transient: 4, period: 1 })));
+        assert_eq!(grid.find_cycle(&Animation::default(), 4), Ok(None));
+        let glider = Grid::from(".#....\n..#...\n###...\n......\n......\n......");
+        let torus = Animation::default().with_boundary(Boundary::Toroidal);
+        assert_eq!(glider.find_cycle(&torus, 100), Ok(Some(Cycle { transient: 0, period: 24 })));
+        assert_eq!(BitGrid::from(&glider).find_cycle(&torus, 100), Ok(Some(Cycle { transient: 0, period: 24 })));
+        let blinker = Grid::from(".....\n..#..\n..#..\n..#..\n.....");
+        assert_eq!(blinker.find_cycle(&Animation::default(), 100), Ok(Some(Cycle { transient: 0, period: 2 })));
+        assert_eq!(blinker.find_cycle(&Animation::default(), 1), Ok(None));
+        let block = Grid::from("....\n.##.\n.##.\n....");
+        assert_eq!(block.find_cycle(&Animation::default(), 0), Ok(None));
+        assert_eq!(block.find_cycle(&Animation::default(), 1), Ok(Some(Cycle { transient: 0, period: 1 })));
+    }
+
+    #[test]
+    fn skipping_cycles() {
+        let glider = Grid::from(".#....\n..#...\n###...\n......\n......\n......");
+        let torus = Animation::default().with_boundary(Boundary::Toroidal);
+        let expected = glider.clone().animate_n_by(1_000_000_000_000 % 24, &torus).unwrap();
+        assert_eq!(glider.clone().animate_n_by(1_000_000_000_000, &torus).unwrap(), expected);
+        let mut bits = BitGrid::from(&glider);
+        bits.animate_n_by(1_000_000_000_000, &torus).unwrap();
+        assert_eq!(Grid::from(&bits), expected);
+        let grid = Grid::from("##.#.#\n...##.\n#....#\n..#...\n#.#..#\n####.#");
+        assert_eq!(grid.clone().xanimate_n(1_000_000_000_000), grid.xanimate_n(1000));
+        let grid = Grid::from(".#.#.#\n...##.\n#....#\n..#...\n#.#..#\n####..");
+        assert_eq!(grid.clone().animate_n(1_000_000_000_000), grid.animate_n(4));
+    }
+
+    #[test]
+    fn parsing_rle() {
+        let (glider, rule) = Grid::from_rle("#N Glider\n#C A comment\nx = 3, y = 3\nbo$2bo$3o!").unwrap();
+        assert_eq!(glider, Grid::from(".#.\n..#\n###"));
+        assert_eq!(rule, Rule::default());
+        let (grid, rule) = Grid::from_rle("x = 4, y = 3, rule = B36/S23\n2o\n$\n$3bo!").unwrap();
+        assert_eq!(grid, Grid::from("##..\n....\n...#"));
+        assert_eq!(rule.to_string(), "B36/S23");
+        assert_eq!(Grid::from_rle("bo$2bo$3o!"), Err("invalid RLE header 'bo$2bo$3o!'".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3\nbo$2bx$3o!"), Err("invalid character 'x' in RLE pattern".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 2\nbo$2bo$3o!"), Err("RLE pattern is larger than 3x2".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3\n4000000000o!"), Err("RLE pattern is larger than 3x3".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3\n4000000000$o!"), Err("RLE pattern is larger than 3x3".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3\n2o2o!"), Err("RLE pattern is larger than 3x3".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3\n99999999999999999999999o!"), Err("RLE pattern is larger than 3x3".to_owned()));
+        assert_eq!(Grid::from_rle("x = 3, y = 3, rule = B9/S23\n3o!"), Err("invalid neighbor count '9' in 'B9'".to_owned()));
+    }
+
+    #[test]
+    fn exporting_rle() {
+        let grid = Grid::from("##..\n....\n...#\n....");
+        assert_eq!(grid.to_rle(&Rule::default()), "x = 4, y = 4, rule = B3/S23\n2o2$3bo!\n");
+        assert_eq!(Grid::from_rle(&grid.to_rle(&Rule::default())).unwrap().0, grid);
+        let grid = Grid::from(include_str!("day18.txt"));
+        let rle = grid.to_rle(&Rule::default());
+        assert!(rle.lines().all(|line| line.len() <= 70));
+        assert_eq!(Grid::from_rle(&rle).unwrap().0, grid);
+    }
+
+    #[test]
+    fn parsing_cells() {
+        let grid = Grid::from_cells("!Name: Glider\n.O\n..O\nOOO\n").unwrap();
+        assert_eq!(grid, Grid::from(".#.\n..#\n###"));
+        assert_eq!(grid.to_cells(), ".O.\n..O\nOOO\n");
+        assert_eq!(Grid::from_cells(".O\n.#"), Err("invalid character '#' in plaintext pattern".to_owned()));
+    }
+
+    #[test]
+    fn placing_patterns() {
+        let (glider, _) = Grid::from_rle("x = 3, y = 3\nbo$2bo$3o!").unwrap();
+        let mut grid = Grid::new(10, 10);
+        grid.place(&glider, 2, 3).unwrap();
+        let mut expected = Grid::new(10, 10);
+        expected.place(&glider, 3, 4).unwrap();
+        assert_eq!(grid.animate_n(4), expected);
+        assert_eq!(Grid::new(10, 10).place(&glider, 8, 0), Err("3x3 pattern at 8,0 doesn't fit into 10x10 grid".to_owned()));
+        // Lightweight spaceship moves two cells every four steps
+        let lwss = Grid::from_cells(".O..O\nO....\nO...O\nOOOO.").unwrap();
+        let mut grid = Grid::new(12, 8);
+        grid.place(&lwss, 6, 2).unwrap();
+        let mut expected = Grid::new(12, 8);
+        expected.place(&lwss, 4, 2).unwrap();
+        assert_eq!(grid.animate_n(4), expected);
+        // Pulsar oscillates with period 3
+        let (pulsar, _) = Grid::from_rle("x = 13, y = 13, rule = B3/S23\n2b3o3b3o2b2$o4bobo4bo$o4bobo4bo$o4bobo4bo$2b3o3b3o2b2$2b3o3b3o2b$o4bobo4bo$o4bobo4bo$o4bobo4bo2$2b3o3b3o!").unwrap();
+        let mut grid = Grid::new(17, 17);
+        grid.place(&pulsar, 2, 2).unwrap();
+        assert_eq!(grid.find_cycle(&Animation::default(), 10), Ok(Some(Cycle { transient: 0, period: 3 })));
+    }
+
+    #[test]
+    fn hashlife() {
+        let grid = Grid::from(".#.#.#\n...##.\n#....#\n..#...\n#.#..#\n####..");
+        let mut life = Hashlife::new(&grid, &Animation::default()).unwrap();
+        assert_eq!(Grid::from(&life), grid);
+        life.animate_n(4);
+        assert_eq!(Grid::from(&life), grid.animate_n(4));
+        let grid = Grid::from("##.#.#\n...##.\n#....#\n..#...\n#.#..#\n####.#");
+        let mut life = Hashlife::new(&grid, &Animation::default().stuck_corners(6, 6)).unwrap();
+        life.animate_n(5);
+        assert_eq!(Grid::from(&life), grid.clone().xanimate_n(5));
+        assert_eq!(life.count(), 17);
+        let animation = Animation::new("B36/S23".parse().unwrap()).pin(2, 3, false).pin(4, 0, true);
+        for n in 1..20 {
+            let mut life = Hashlife::new(&grid, &animation).unwrap();
+            life.animate_n(n);
+            assert_eq!(Grid::from(&life), grid.clone().animate_n_by(n, &animation).unwrap());
+        }
+        assert!(Hashlife::new(&grid, &Animation::default().with_boundary(Boundary::Toroidal)).is_err());
+    }
+
+    #[test]
+    fn hashlife_complete() {
+        let grid = Grid::from(include_str!("day18.txt"));
+        let mut life = Hashlife::new(&grid, &Animation::default()).unwrap();
+        life.animate_n(100);
+        assert_eq!(Grid::from(&life), grid.clone().animate_n(100));
+        let mut life = Hashlife::new(&grid, &Animation::default().stuck_corners(100, 100)).unwrap();
+        life.animate_n(100);
+        assert_eq!(Grid::from(&life), grid.xanimate_n(100));
+    }
+
+    #[test]
+    fn hashlife_long() {
+        let (pulsar, _) = Grid::from_rle("x = 13, y = 13, rule = B3/S23\n2b3o3b3o2b2$o4bobo4bo$o4bobo4bo$o4bobo4bo$2b3o3b3o2b2$2b3o3b3o2b$o4bobo4bo$o4bobo4bo$o4bobo4bo2$2b3o3b3o!").unwrap();
+        let (glider, _) = Grid::from_rle("x = 3, y = 3\nbo$2bo$3o!").unwrap();
+        let mut grid = Grid::new(40, 30);
+        grid.place(&pulsar, 2, 2).unwrap();
+        grid.place(&glider, 20, 10).unwrap();
+        let mut life = Hashlife::new(&grid, &Animation::default()).unwrap();
+        life.animate_n(1_000_000_000_000);
+        assert_eq!(Grid::from(&life), grid.animate_n(1_000_000_000_000));
+    }
 }
@@ -48,6 +48,74 @@ impl<'a> Medicine<'a> {
     fn count_distinct_molecules(&self) -> usize {
         HashSet::<String>::from_iter(self.molecules()).len()
     }
+
+    /// Reduces the molecule back to `e` by applying replacements in reverse,
+    /// trying replacements in the given order. Replacements from `e` are only
+    /// used for the last step. Returns the number of steps, or None if it gets
+    /// stuck or runs into a molecule it has already seen, where it would go
+    /// around in circles.
+    fn reduce(&self, replacements: &[&(&str, &str)]) -> Option<usize> {
+        let mut molecule = self.molecule.to_owned();
+        let mut seen = HashSet::new();
+        let mut steps = 0;
+        while molecule != "e" {
+            if !seen.insert(molecule.clone()) {
+                return None;
+            }
+            let replacement = replacements.iter().find(|&&&(from, to)| {
+                if from == "e" { molecule == to } else { molecule.contains(to) }
+            });
+            match replacement {
+                Some(&&(from, to)) => molecule = molecule.replacen(to, from, 1),
+                None => return None,
+            }
+            steps += 1;
+        }
+        Some(steps)
+    }
+
+    /// Number of replacement steps to make the molecule, starting from `e`.
+    /// Greedily reduces the molecule, preferring long replacements, and
+    /// restarts with a shuffled order whenever a reduction gets stuck.
+    /// Replacements that shrink the molecule are never used, so that every
+    /// reduction ends. With unambiguous replacements like in the puzzle, every
+    /// reduction takes the same number of steps, which is then the fewest.
+    /// Otherwise this is the fewest of several reductions, but not necessarily
+    /// the fewest possible.
+    pub fn steps_to_make(&self) -> Option<usize> {
+        let mut replacements: Vec<&(&str, &str)> = self.replacements.iter().filter(|&&(from, to)| {
+            from == "e" || to.len() >= from.len()
+        }).collect();
+        replacements.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+        let mut rng = XorShift(0x2545f4914f6cdd1d);
+        let mut fewest = None;
+        for _ in 0..100 {
+            if let Some(steps) = self.reduce(&replacements) {
+                fewest = Some(fewest.map_or(steps, |fewest: usize| fewest.min(steps)));
+            }
+            rng.shuffle(&mut replacements);
+        }
+        fewest
+    }
+}
+
+/// Xorshift pseudo random number generator, to shuffle replacements
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
 }
 
 pub struct Molecules<'a> {
@@ -95,6 +163,10 @@ impl<'a> Iterator for Molecules<'a> {
 fn main() {
     let medicine = Medicine::from(include_str!("day19.txt"));
     println!("Number of distinct molecules: {}", medicine.count_distinct_molecules());
+    match medicine.steps_to_make() {
+        Some(steps) => println!("Fewest steps to make the medicine: {}", steps),
+        None => println!("Failed to find steps to make the medicine"),
+    }
 }
 
 #[cfg(test)]
@@ -132,4 +204,28 @@ mod tests {
         assert_eq!(medicine.molecules().count(), 9);
         assert_eq!(medicine.count_distinct_molecules(), 7);
     }
+
+    #[test]
+    fn making_molecules() {
+        let medicine = Medicine::from("e => H\ne => O\nH => HO\nH => OH\nO => HH\n\nHOH");
+        assert_eq!(medicine.steps_to_make(), Some(3));
+        let medicine = Medicine::from("e => H\ne => O\nH => HO\nH => OH\nO => HH\n\nHOHOHO");
+        assert_eq!(medicine.steps_to_make(), Some(6));
+        let medicine = Medicine::from("e => H\nH => HO\n\nOH");
+        assert_eq!(medicine.steps_to_make(), None);
+        let medicine = Medicine::from("e => H\nH => O\nO => H\n\nHO");
+        assert_eq!(medicine.steps_to_make(), None);
+        let medicine = Medicine::from("e => H\nH => O\n\nO");
+        assert_eq!(medicine.steps_to_make(), Some(2));
+    }
+
+    #[test]
+    fn making_medicine() {
+        // Every replacement adds one element, or Rn...Ar with additional ones
+        // separated by Y, so the count of elements gives the number of steps
+        let medicine = Medicine::from(include_str!("day19.txt"));
+        let elements = medicine.molecule.chars().filter(|c| c.is_uppercase()).count();
+        let count = |element| medicine.molecule.matches(element).count();
+        assert_eq!(medicine.steps_to_make(), Some(elements - count("Rn") - count("Ar") - 2 * count("Y") - 1));
+    }
 }
@@ -1,8 +1,17 @@
 #[macro_use]
 extern crate nom;
+extern crate num_bigint;
+extern crate num_traits;
 
-use std::str::{self, FromStr};
-use nom::{digit, space, eol};
+use std::{env, fmt, str, usize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use nom::{IResult, digit, space, eol};
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero, One};
 
 named!(offset<isize>,
     map_res!(
@@ -17,27 +26,112 @@ named!(offset<isize>,
     )
 );
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Register {
-    A,
-    B,
+named!(immediate<isize>,
+    alt!(
+        offset |
+        map_res!(map_res!(digit, str::from_utf8), FromStr::from_str)
+    )
+);
+
+/// Register, named by a lowercase letter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Register(u8);
+
+pub const NUM_REGISTERS: usize = 26;
+
+impl Register {
+    /// Register with the given name, if it's a lowercase letter
+    pub fn new(name: char) -> Option<Register> {
+        if name.is_ascii_lowercase() {
+            Some(Register(name as u8))
+        } else {
+            None
+        }
+    }
+
+    fn index(&self) -> usize {
+        (self.0 - b'a') as usize
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0 as char)
+    }
 }
 
 named!(register<Register>,
+    map!(one_of!("abcdefghijklmnopqrstuvwxyz"), |c| Register(c as u8))
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Reg(Register),
+    Imm(isize),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(reg) => reg.fmt(f),
+            Operand::Imm(n) => n.fmt(f),
+        }
+    }
+}
+
+named!(operand<Operand>,
     alt!(
-        value!(Register::A, tag!("a")) |
-        value!(Register::B, tag!("b"))
+        map!(register, |reg| Operand::Reg(reg)) |
+        map!(immediate, |n| Operand::Imm(n))
     )
 );
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Instruction {
     Hlf(Register),
     Tpl(Register),
     Inc(Register),
+    Dec(Register),
+    Cpy(Operand, Register),
     Jmp(isize),
     Jie(Register, isize),
     Jio(Register, isize),
+    Jnz(Operand, Operand),
+    Out(Operand),
+}
+
+impl Instruction {
+    fn registers(&self) -> Vec<Register> {
+        let operands = match *self {
+            Instruction::Hlf(reg) | Instruction::Tpl(reg) | Instruction::Inc(reg) | Instruction::Dec(reg) => vec![Operand::Reg(reg)],
+            Instruction::Cpy(op, reg) => vec![op, Operand::Reg(reg)],
+            Instruction::Jmp(_) => vec![],
+            Instruction::Jie(reg, _) | Instruction::Jio(reg, _) => vec![Operand::Reg(reg)],
+            Instruction::Jnz(op1, op2) => vec![op1, op2],
+            Instruction::Out(op) => vec![op],
+        };
+        operands.into_iter().filter_map(|op| match op {
+            Operand::Reg(reg) => Some(reg),
+            Operand::Imm(_) => None,
+        }).collect()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Hlf(reg) => write!(f, "hlf {}", reg),
+            Instruction::Tpl(reg) => write!(f, "tpl {}", reg),
+            Instruction::Inc(reg) => write!(f, "inc {}", reg),
+            Instruction::Dec(reg) => write!(f, "dec {}", reg),
+            Instruction::Cpy(op, reg) => write!(f, "cpy {} {}", op, reg),
+            Instruction::Jmp(ofs) => write!(f, "jmp {:+}", ofs),
+            Instruction::Jie(reg, ofs) => write!(f, "jie {}, {:+}", reg, ofs),
+            Instruction::Jio(reg, ofs) => write!(f, "jio {}, {:+}", reg, ofs),
+            Instruction::Jnz(op1, op2) => write!(f, "jnz {} {}", op1, op2),
+            Instruction::Out(op) => write!(f, "out {}", op),
+        }
+    }
 }
 
 named!(instruction<Instruction>,
@@ -45,9 +139,13 @@ named!(instruction<Instruction>,
         chain!(tag!("hlf") ~ space ~ reg: register, || Instruction::Hlf(reg)) |
         chain!(tag!("tpl") ~ space ~ reg: register, || Instruction::Tpl(reg)) |
         chain!(tag!("inc") ~ space ~ reg: register, || Instruction::Inc(reg)) |
+        chain!(tag!("dec") ~ space ~ reg: register, || Instruction::Dec(reg)) |
+        chain!(tag!("cpy") ~ space ~ op: operand ~ space ~ reg: register, || Instruction::Cpy(op, reg)) |
         chain!(tag!("jmp") ~ space ~ ofs: offset, || Instruction::Jmp(ofs)) |
         chain!(tag!("jie") ~ space ~ reg: register ~ tag!(",") ~ space ~ ofs: offset, || Instruction::Jie(reg, ofs)) |
-        chain!(tag!("jio") ~ space ~ reg: register ~ tag!(",") ~ space ~ ofs: offset, || Instruction::Jio(reg, ofs))
+        chain!(tag!("jio") ~ space ~ reg: register ~ tag!(",") ~ space ~ ofs: offset, || Instruction::Jio(reg, ofs)) |
+        chain!(tag!("jnz") ~ space ~ op1: operand ~ space ~ op2: operand, || Instruction::Jnz(op1, op2)) |
+        chain!(tag!("out") ~ space ~ op: operand, || Instruction::Out(op))
     )
 );
 
@@ -60,108 +158,1314 @@ named!(pub program<Vec<Instruction> >,
     )
 );
 
+impl Instruction {
+    /// Offset of a jump that doesn't depend on register values
+    fn jump_offset(&self) -> Option<isize> {
+        match *self {
+            Instruction::Jmp(ofs) | Instruction::Jie(_, ofs) | Instruction::Jio(_, ofs) => Some(ofs),
+            Instruction::Jnz(_, Operand::Imm(ofs)) => Some(ofs),
+            _ => None,
+        }
+    }
+
+    /// Absolute jump target of an instruction at the given address, if it
+    /// is within the program or right after its end
+    fn jump_target(&self, ip: usize, len: usize) -> Option<usize> {
+        self.jump_offset().and_then(|ofs| {
+            let target = ip as isize + ofs;
+            if target >= 0 && target as usize <= len { Some(target as usize) } else { None }
+        })
+    }
+
+    /// Like Display, but with the jump target replaced by the given label
+    fn with_label(&self, label: &str) -> String {
+        match *self {
+            Instruction::Jmp(_) => format!("jmp {}", label),
+            Instruction::Jie(reg, _) => format!("jie {}, {}", reg, label),
+            Instruction::Jio(reg, _) => format!("jio {}, {}", reg, label),
+            Instruction::Jnz(op, _) => format!("jnz {} {}", op, label),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Same jump with a different offset
+    fn with_offset(&self, ofs: isize) -> Instruction {
+        match *self {
+            Instruction::Jmp(_) => Instruction::Jmp(ofs),
+            Instruction::Jie(reg, _) => Instruction::Jie(reg, ofs),
+            Instruction::Jio(reg, _) => Instruction::Jio(reg, ofs),
+            Instruction::Jnz(op, _) => Instruction::Jnz(op, Operand::Imm(ofs)),
+            ins => ins,
+        }
+    }
+}
+
+/// Lists the program with labels at every jump target. Labels are named by
+/// the absolute address of the target, so `jmp L7` jumps to instruction 7.
+/// Jumps by register values and jumps out of the program keep their offsets.
+pub fn disassemble(prog: &[Instruction]) -> String {
+    let targets: HashSet<usize> = prog.iter().enumerate().filter_map(|(ip, ins)| ins.jump_target(ip, prog.len())).collect();
+    let label = |addr: usize| if targets.contains(&addr) { format!("L{}:", addr) } else { String::new() };
+    let mut text: String = prog.iter().enumerate().map(|(ip, ins)| {
+        let ins = match ins.jump_target(ip, prog.len()) {
+            Some(target) => ins.with_label(&format!("L{}", target)),
+            None => ins.to_string(),
+        };
+        format!("{:<6}{}\n", label(ip), ins)
+    }).collect();
+    if targets.contains(&prog.len()) {
+        text.push_str(&label(prog.len()));
+        text.push('\n');
+    }
+    text
+}
+
+/// Labels are alphanumeric, start with a letter and can't be mistaken for a register
+fn label_name(s: &str) -> Result<&str, ()> {
+    let first = s.chars().next().unwrap_or('0');
+    if first.is_alphabetic() && !(s.len() == 1 && first.is_lowercase()) { Ok(s) } else { Err(()) }
+}
+
+named!(label<&str>,
+    map_res!(map_res!(nom::alphanumeric, str::from_utf8), label_name)
+);
+
+// Instruction that may jump to a label instead of an offset, in which case
+// the offset is filled in later
+named!(labeled_instruction<(Instruction, Option<&str>)>,
+    alt!(
+        chain!(tag!("jmp") ~ space ~ l: label, || (Instruction::Jmp(0), Some(l))) |
+        chain!(tag!("jie") ~ space ~ reg: register ~ tag!(",") ~ space ~ l: label, || (Instruction::Jie(reg, 0), Some(l))) |
+        chain!(tag!("jio") ~ space ~ reg: register ~ tag!(",") ~ space ~ l: label, || (Instruction::Jio(reg, 0), Some(l))) |
+        chain!(tag!("jnz") ~ space ~ op: operand ~ space ~ l: label, || (Instruction::Jnz(op, Operand::Imm(0)), Some(l))) |
+        map!(instruction, |ins| (ins, None))
+    )
+);
+
+named!(assembly_line<(Option<&str>, Option<(Instruction, Option<&str>)>)>,
+    chain!(
+        l: opt!(complete!(chain!(l: label ~ char!(':') ~ opt!(complete!(space)), || l))) ~
+        ins: opt!(complete!(labeled_instruction)) ~
+        opt!(complete!(space)) ~
+        opt!(complete!(chain!(char!(';') ~ opt!(complete!(is_not!("\n"))), || ()))),
+        || (l, ins)
+    )
+);
+
+/// Parses a program that may use labels (`loop:` in front of an instruction
+/// or on a line by itself) as jump targets, and `;` comments
+pub fn assemble(text: &str) -> Result<Vec<Instruction>, String> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        match assembly_line(line.trim().as_bytes()) {
+            IResult::Done(rest, (l, ins)) if rest == [] => {
+                if let Some(name) = l {
+                    if labels.insert(name, lines.len()).is_some() {
+                        return Err(format!("label {} defined twice on line {}", name, n + 1));
+                    }
+                }
+                if let Some((ins, target)) = ins {
+                    lines.push((n, ins, target));
+                }
+            },
+            _ => return Err(format!("invalid instruction on line {}: {}", n + 1, line.trim())),
+        }
+    }
+    lines.iter().enumerate().map(|(ip, &(n, ins, target))| match target {
+        Some(name) => match labels.get(name) {
+            Some(&addr) => Ok(ins.with_offset(addr as isize - ip as isize)),
+            None => Err(format!("unknown label {} on line {}", name, n + 1)),
+        },
+        None => Ok(ins),
+    }).collect()
+}
+
+/// What to do if an arithmetic operation exceeds the range of a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Wrap,
+    Saturate,
+    Trap,
+}
+
+/// Value of a register. Operations return None if they overflow and the
+/// overflow policy is to trap. Unbounded words only overflow when going
+/// below zero, which saturates to zero for both wrapping and saturating.
+pub trait Word: Clone + Eq + Hash + fmt::Debug + fmt::Display {
+    fn from_isize(n: isize, overflow: Overflow) -> Option<Self>;
+    /// Value as a jump offset. Fixed-width words are read as two's complement
+    /// numbers of their width, unbounded words as they are; None if the
+    /// offset does not fit in an isize.
+    fn to_offset(&self) -> Option<isize>;
+    fn is_zero(&self) -> bool;
+    fn is_one(&self) -> bool;
+    fn is_even(&self) -> bool;
+    fn half(&self) -> Self;
+    fn triple(&self, overflow: Overflow) -> Option<Self>;
+    fn incr(&self, overflow: Overflow) -> Option<Self>;
+    fn decr(&self, overflow: Overflow) -> Option<Self>;
+}
+
+macro_rules! word {
+    ($t:ty, $signed:ty) => {
+        impl Word for $t {
+            fn from_isize(n: isize, overflow: Overflow) -> Option<$t> {
+                if n >= 0 && n as u128 <= <$t>::max_value() as u128 {
+                    return Some(n as $t);
+                }
+                match overflow {
+                    Overflow::Wrap => Some(n as $t),
+                    Overflow::Saturate => Some(if n < 0 { 0 } else { <$t>::max_value() }),
+                    Overflow::Trap => None,
+                }
+            }
+
+            fn to_offset(&self) -> Option<isize> {
+                let n = *self as $signed as i128;
+                if n >= isize::min_value() as i128 && n <= isize::max_value() as i128 { Some(n as isize) } else { None }
+            }
+
+            fn is_zero(&self) -> bool { *self == 0 }
+            fn is_one(&self) -> bool { *self == 1 }
+            fn is_even(&self) -> bool { *self % 2 == 0 }
+            fn half(&self) -> $t { *self / 2 }
+
+            fn triple(&self, overflow: Overflow) -> Option<$t> {
+                match overflow {
+                    Overflow::Wrap => Some(self.wrapping_mul(3)),
+                    Overflow::Saturate => Some(self.saturating_mul(3)),
+                    Overflow::Trap => self.checked_mul(3),
+                }
+            }
+
+            fn incr(&self, overflow: Overflow) -> Option<$t> {
+                match overflow {
+                    Overflow::Wrap => Some(self.wrapping_add(1)),
+                    Overflow::Saturate => Some(self.saturating_add(1)),
+                    Overflow::Trap => self.checked_add(1),
+                }
+            }
+
+            fn decr(&self, overflow: Overflow) -> Option<$t> {
+                match overflow {
+                    Overflow::Wrap => Some(self.wrapping_sub(1)),
+                    Overflow::Saturate => Some(self.saturating_sub(1)),
+                    Overflow::Trap => self.checked_sub(1),
+                }
+            }
+        }
+    }
+}
+
+word!(u8, i8);
+word!(u32, i32);
+word!(u64, i64);
+word!(u128, i128);
+word!(usize, isize);
+
+impl Word for BigUint {
+    fn from_isize(n: isize, overflow: Overflow) -> Option<BigUint> {
+        match overflow {
+            _ if n >= 0 => Some(BigUint::from(n as u64)),
+            Overflow::Wrap | Overflow::Saturate => Some(BigUint::zero()),
+            Overflow::Trap => None,
+        }
+    }
+
+    fn to_offset(&self) -> Option<isize> { ToPrimitive::to_isize(self) }
+    fn is_zero(&self) -> bool { Zero::is_zero(self) }
+    fn is_one(&self) -> bool { *self == BigUint::one() }
+    fn is_even(&self) -> bool { Zero::is_zero(&(self % &BigUint::from(2u64))) }
+    fn half(&self) -> BigUint { self.clone() / 2u32 }
+
+    fn triple(&self, _: Overflow) -> Option<BigUint> {
+        Some(self.clone() * 3u32)
+    }
+
+    fn incr(&self, _: Overflow) -> Option<BigUint> {
+        Some(self.clone() + 1u32)
+    }
+
+    fn decr(&self, overflow: Overflow) -> Option<BigUint> {
+        match overflow {
+            _ if !Zero::is_zero(self) => Some(self.clone() - 1u32),
+            Overflow::Wrap | Overflow::Saturate => Some(BigUint::zero()),
+            Overflow::Trap => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
-pub struct Vm {
+pub struct Vm<W> {
     prog: Vec<Instruction>,
-    a: usize,
-    b: usize,
+    regs: Vec<W>,
     ip: usize,
+    output: Vec<W>,
+    overflow: Overflow,
 }
 
-impl<'a> From<&'a str> for Vm {
-    fn from(s: &str) -> Vm {
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status {
+    Halted,
+    StepLimit,
+    InvalidJump(usize),
+    Cycle { ip: usize, period: usize },
+    Overflow { ip: usize, instruction: Instruction },
+}
+
+impl<'a, W: Word> From<&'a str> for Vm<W> {
+    fn from(s: &str) -> Vm<W> {
         Vm {
             prog: program(s.as_bytes()).unwrap().1,
-            a: 0,
-            b: 0,
+            regs: vec![W::from_isize(0, Overflow::Trap).unwrap(); NUM_REGISTERS],
             ip: 0,
+            output: Vec::new(),
+            overflow: Overflow::Trap,
         }
     }
 }
 
-impl Vm {
-    fn jump(&mut self, offset: isize) {
-        if offset < 0 {
-            self.ip -= (-offset) as usize;
+impl<W: Word> Vm<W> {
+    pub fn with_overflow(mut self, overflow: Overflow) -> Vm<W> {
+        self.overflow = overflow;
+        self
+    }
+
+    fn jump(&mut self, offset: isize) -> Result<(), Status> {
+        let target = if offset < 0 {
+            self.ip.checked_sub((offset as usize).wrapping_neg())
         } else {
-            self.ip += offset as usize;
+            self.ip.checked_add(offset as usize)
+        };
+        match target {
+            Some(ip) => { self.ip = ip; Ok(()) },
+            None => Err(Status::InvalidJump(self.ip)),
         }
     }
 
+    fn get(&self, reg: Register) -> W {
+        self.regs[reg.index()].clone()
+    }
+
+    fn set(&mut self, reg: Register, value: W) {
+        self.regs[reg.index()] = value;
+    }
+
+    fn trap(&self) -> Status {
+        Status::Overflow { ip: self.ip, instruction: self.prog[self.ip] }
+    }
+
+    fn value(&self, op: Operand) -> Result<W, Status> {
+        match op {
+            Operand::Reg(reg) => Ok(self.get(reg)),
+            Operand::Imm(n) => W::from_isize(n, self.overflow).ok_or_else(|| self.trap()),
+        }
+    }
+
+    fn offset(&self, op: Operand) -> Result<isize, Status> {
+        match op {
+            Operand::Reg(reg) => self.regs[reg.index()].to_offset().ok_or(Status::InvalidJump(self.ip)),
+            Operand::Imm(n) => Ok(n),
+        }
+    }
+
+    fn update<F: Fn(&W, Overflow) -> Option<W>>(&mut self, reg: Register, f: F) -> Result<(), Status> {
+        match f(&self.regs[reg.index()], self.overflow) {
+            Some(value) => { self.set(reg, value); self.jump(1) },
+            None => Err(self.trap()),
+        }
+    }
+
+    /// Registers used by the program, in alphabetical order
+    fn registers(&self) -> Vec<Register> {
+        let mut regs: Vec<Register> = self.prog.iter().flat_map(|ins| ins.registers()).collect();
+        regs.sort();
+        regs.dedup();
+        regs
+    }
+
     fn done(&self) -> bool {
         self.ip >= self.prog.len()
     }
 
-    fn step(&mut self) {
+    fn step(&mut self) -> Result<(), Status> {
         match self.prog[self.ip] {
-            Instruction::Hlf(Register::A) => { self.a /= 2; self.jump(1); },
-            Instruction::Hlf(Register::B) => { self.b /= 2; self.jump(1); },
-            Instruction::Tpl(Register::A) => { self.a *= 3; self.jump(1); },
-            Instruction::Tpl(Register::B) => { self.b *= 3; self.jump(1); },
-            Instruction::Inc(Register::A) => { self.a += 1; self.jump(1); },
-            Instruction::Inc(Register::B) => { self.b += 1; self.jump(1); },
+            Instruction::Hlf(reg) => self.update(reg, |v, _| Some(v.half())),
+            Instruction::Tpl(reg) => self.update(reg, |v, overflow| v.triple(overflow)),
+            Instruction::Inc(reg) => self.update(reg, |v, overflow| v.incr(overflow)),
+            Instruction::Dec(reg) => self.update(reg, |v, overflow| v.decr(overflow)),
+            Instruction::Cpy(op, reg) => { let value = try!(self.value(op)); self.set(reg, value); self.jump(1) },
             Instruction::Jmp(ofs) => self.jump(ofs),
-            Instruction::Jie(Register::A, ofs) => if self.a % 2 == 0 { self.jump(ofs) } else { self.jump(1) },
-            Instruction::Jie(Register::B, ofs) => if self.b % 2 == 0 { self.jump(ofs) } else { self.jump(1) },
-            Instruction::Jio(Register::A, ofs) => if self.a == 1 { self.jump(ofs) } else { self.jump(1) },
-            Instruction::Jio(Register::B, ofs) => if self.b == 1 { self.jump(ofs) } else { self.jump(1) },
+            Instruction::Jie(reg, ofs) => if self.regs[reg.index()].is_even() { self.jump(ofs) } else { self.jump(1) },
+            Instruction::Jio(reg, ofs) => if self.regs[reg.index()].is_one() { self.jump(ofs) } else { self.jump(1) },
+            Instruction::Jnz(op1, op2) => if !try!(self.value(op1)).is_zero() { let ofs = try!(self.offset(op2)); self.jump(ofs) } else { self.jump(1) },
+            Instruction::Out(op) => { let value = try!(self.value(op)); self.output.push(value); self.jump(1) },
         }
     }
 
-    fn run(&mut self) {
+    pub fn run(&mut self) -> Status {
+        self.run_with_limit(usize::MAX)
+    }
+
+    /// Runs the program until it halts, but for no more than the given number of steps
+    pub fn run_with_limit(&mut self, max_steps: usize) -> Status {
+        for _ in 0..max_steps {
+            if self.done() {
+                return Status::Halted;
+            }
+            if let Err(status) = self.step() {
+                return status;
+            }
+        }
+        if self.done() { Status::Halted } else { Status::StepLimit }
+    }
+
+    /// Like run_with_limit, but remembers every state to detect when the
+    /// program enters an infinite loop
+    pub fn run_until_cycle(&mut self, max_steps: usize) -> Status {
+        let mut seen = HashMap::new();
+        for n in 0..max_steps {
+            if self.done() {
+                return Status::Halted;
+            }
+            if let Some(m) = seen.insert((self.ip, self.regs.clone()), n) {
+                return Status::Cycle { ip: self.ip, period: n - m };
+            }
+            if let Err(status) = self.step() {
+                return status;
+            }
+        }
+        if self.done() { Status::Halted } else { Status::StepLimit }
+    }
+
+    /// Like run_with_limit, but writes the instruction pointer, instruction
+    /// and registers used by the program before every step, tab separated
+    pub fn run_traced<T: Write>(&mut self, max_steps: usize, out: &mut T) -> io::Result<Status> {
+        let regs = self.registers();
+        let names: Vec<String> = regs.iter().map(|reg| reg.to_string()).collect();
+        try!(writeln!(out, "ip\tinstruction\t{}", names.join("\t")));
+        for _ in 0..max_steps {
+            if self.done() {
+                return Ok(Status::Halted);
+            }
+            let values: Vec<String> = regs.iter().map(|&reg| self.get(reg).to_string()).collect();
+            try!(writeln!(out, "{}\t{}\t{}", self.ip, self.prog[self.ip], values.join("\t")));
+            if let Err(status) = self.step() {
+                return Ok(status);
+            }
+        }
+        Ok(if self.done() { Status::Halted } else { Status::StepLimit })
+    }
+
+    /// Evaluates a Collatz loop at its header until the value reaches 1 (or
+    /// 0, where it never ends) or the next iteration would overflow. The
+    /// instruction pointer stays at the header, so interpreting from there
+    /// exits the loop or traps where the program would.
+    fn collatz(&mut self, collatz: &Collatz) {
+        let mut n = self.get(collatz.value);
+        let mut steps = self.get(collatz.counter);
+        while !n.is_one() && !n.is_zero() {
+            let next = if n.is_even() {
+                Some(n.half())
+            } else {
+                n.triple(self.overflow).and_then(|n| n.incr(self.overflow))
+            };
+            match (next, steps.incr(self.overflow)) {
+                (Some(next), Some(incremented)) => { n = next; steps = incremented; },
+                _ => break,
+            }
+        }
+        self.set(collatz.value, n);
+        self.set(collatz.counter, steps);
+    }
+
+    /// Like run, but evaluates Collatz loops directly instead of stepping
+    /// through them
+    pub fn run_shortcut(&mut self) -> Status {
+        let loops: HashMap<usize, Collatz> = Cfg::new(&self.prog).collatz_loops().into_iter().map(|collatz| (collatz.header, collatz)).collect();
         while !self.done() {
-            self.step();
+            if let Some(collatz) = loops.get(&self.ip) {
+                self.collatz(collatz);
+            }
+            if let Err(status) = self.step() {
+                return status;
+            }
+        }
+        Status::Halted
+    }
+
+    fn target(&self, ip: usize, offset: isize) -> Option<usize> {
+        match (ip as isize).checked_add(offset) {
+            Some(target) if target >= 0 => Some(target as usize),
+            _ => None,
+        }
+    }
+
+    /// Translates the program into operations with resolved register indices
+    /// and absolute jump targets. Anything that can't be resolved ahead of time
+    /// (jumps by register values, out of range immediates or jumps before the
+    /// start) falls back to interpreting the original instruction. Register
+    /// updates directly followed by a jump are fused into one operation, since
+    /// most of the time goes into dispatching operations rather than executing
+    /// them.
+    pub fn compile(&self) -> Compiled<W> {
+        let mut ops: Vec<Op<W>> = self.prog.iter().enumerate().map(|(ip, ins)| {
+            let next = ip + 1;
+            match *ins {
+                Instruction::Hlf(reg) => Op::Update(Update::Half, reg.index()),
+                Instruction::Tpl(reg) => Op::Update(Update::Triple, reg.index()),
+                Instruction::Inc(reg) => Op::Update(Update::Incr, reg.index()),
+                Instruction::Dec(reg) => Op::Update(Update::Decr, reg.index()),
+                Instruction::Cpy(Operand::Reg(src), reg) => Op::Copy(src.index(), reg.index()),
+                Instruction::Cpy(Operand::Imm(n), reg) => match W::from_isize(n, self.overflow) {
+                    Some(value) => Op::Load(value, reg.index()),
+                    None => Op::Interpret,
+                },
+                Instruction::Jmp(ofs) => self.target(ip, ofs).map_or(Op::Interpret, Op::Jump),
+                Instruction::Jie(reg, ofs) => self.target(ip, ofs).map_or(Op::Interpret, |target| Op::JumpIf(Cond::Even, reg.index(), target, next)),
+                Instruction::Jio(reg, ofs) => self.target(ip, ofs).map_or(Op::Interpret, |target| Op::JumpIf(Cond::One, reg.index(), target, next)),
+                Instruction::Jnz(Operand::Imm(n), ofs) => match (W::from_isize(n, self.overflow), ofs) {
+                    (Some(ref value), _) if value.is_zero() => Op::Jump(next),
+                    (Some(_), Operand::Imm(ofs)) => self.target(ip, ofs).map_or(Op::Interpret, Op::Jump),
+                    _ => Op::Interpret,
+                },
+                Instruction::Jnz(Operand::Reg(reg), Operand::Imm(ofs)) => self.target(ip, ofs).map_or(Op::Interpret, |target| Op::JumpIf(Cond::NonZero, reg.index(), target, next)),
+                Instruction::Jnz(Operand::Reg(_), Operand::Reg(_)) => Op::Interpret,
+                Instruction::Out(Operand::Reg(reg)) => Op::Out(reg.index()),
+                Instruction::Out(Operand::Imm(_)) => Op::Interpret,
+            }
+        }).collect();
+        for ip in 1..ops.len() {
+            ops[ip - 1] = match (ops[ip - 1].clone(), ops[ip].clone()) {
+                (Op::Update(update, r), Op::Jump(target)) => Op::UpdateJump(update, r, target),
+                (Op::Update(update, r), Op::JumpIf(cond, c, target, next)) if c == r => Op::UpdateJumpIf(update, r, cond, target, next),
+                (op, _) => op,
+            };
+        }
+        Compiled { ops: ops }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Update {
+    Half,
+    Triple,
+    Incr,
+    Decr,
+}
+
+impl Update {
+    fn apply<W: Word>(self, value: &W, overflow: Overflow) -> Option<W> {
+        match self {
+            Update::Half => Some(value.half()),
+            Update::Triple => value.triple(overflow),
+            Update::Incr => value.incr(overflow),
+            Update::Decr => value.decr(overflow),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond {
+    Even,
+    One,
+    NonZero,
+}
+
+impl Cond {
+    fn test<W: Word>(self, value: &W) -> bool {
+        match self {
+            Cond::Even => value.is_even(),
+            Cond::One => value.is_one(),
+            Cond::NonZero => !value.is_zero(),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op<W> {
+    Update(Update, usize),
+    Load(W, usize),
+    Copy(usize, usize),
+    Jump(usize),
+    JumpIf(Cond, usize, usize, usize),
+    /// Update followed by the jump at the next address
+    UpdateJump(Update, usize, usize),
+    /// Update followed by a conditional jump on the updated register
+    UpdateJumpIf(Update, usize, Cond, usize, usize),
+    Out(usize),
+    Interpret,
+}
+
+/// Program compiled for a Vm, see Vm::compile
+pub struct Compiled<W> {
+    ops: Vec<Op<W>>,
+}
+
+impl<W: Word> Compiled<W> {
+    pub fn run(&self, vm: &mut Vm<W>) -> Status {
+        self.run_with_limit(vm, usize::MAX)
+    }
+
+    /// Runs the compiled program on the given Vm, which must be the one it was
+    /// compiled from. Results are the same as with Vm::run_with_limit.
+    pub fn run_with_limit(&self, vm: &mut Vm<W>, max_steps: usize) -> Status {
+        let mut ip = vm.ip;
+        let mut steps = 0;
+        while steps < max_steps {
+            steps += 1;
+            let op = match self.ops.get(ip) {
+                Some(op) => op,
+                None => { vm.ip = ip; return Status::Halted; },
+            };
+            match *op {
+                Op::Update(update, r) => match update.apply(&vm.regs[r], vm.overflow) {
+                    Some(value) => { vm.regs[r] = value; ip += 1 },
+                    None => { vm.ip = ip; return vm.trap() },
+                },
+                Op::Load(ref value, r) => { vm.regs[r] = value.clone(); ip += 1 },
+                Op::Copy(src, r) => { vm.regs[r] = vm.regs[src].clone(); ip += 1 },
+                Op::Jump(target) => ip = target,
+                Op::JumpIf(cond, r, target, next) => ip = if cond.test(&vm.regs[r]) { target } else { next },
+                Op::UpdateJump(update, r, target) => match update.apply(&vm.regs[r], vm.overflow) {
+                    Some(value) if steps < max_steps => { vm.regs[r] = value; ip = target; steps += 1 },
+                    Some(value) => { vm.regs[r] = value; ip += 1 },
+                    None => { vm.ip = ip; return vm.trap() },
+                },
+                Op::UpdateJumpIf(update, r, cond, target, next) => match update.apply(&vm.regs[r], vm.overflow) {
+                    Some(value) if steps < max_steps => {
+                        ip = if cond.test(&value) { target } else { next };
+                        vm.regs[r] = value;
+                        steps += 1
+                    },
+                    Some(value) => { vm.regs[r] = value; ip += 1 },
+                    None => { vm.ip = ip; return vm.trap() },
+                },
+                Op::Out(r) => { vm.output.push(vm.regs[r].clone()); ip += 1 },
+                Op::Interpret => {
+                    vm.ip = ip;
+                    if let Err(status) = vm.step() {
+                        return status;
+                    }
+                    ip = vm.ip;
+                },
+            }
+        }
+        vm.ip = ip;
+        if vm.done() { Status::Halted } else { Status::StepLimit }
+    }
+}
+
+/// Sequence of instructions that is only entered at its start and only left
+/// at its end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    /// Start addresses of the blocks that can follow, where the program
+    /// length means halting
+    pub successors: Vec<usize>,
+    /// Whether the block ends with a jump by a register value, which can go anywhere
+    pub indirect: bool,
+}
+
+/// Natural loop, given by the start addresses of its blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: usize,
+    pub blocks: Vec<usize>,
+}
+
+/// Loop that counts the steps of the Collatz sequence until the value reaches
+/// 1, as in the puzzle input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collatz {
+    pub header: usize,
+    pub value: Register,
+    pub counter: Register,
+}
+
+impl Collatz {
+    fn new(prog: &[Instruction], header: usize) -> Option<Collatz> {
+        let (value, counter) = match (prog.get(header), prog.get(header + 1)) {
+            (Some(&Instruction::Jio(value, 8)), Some(&Instruction::Inc(counter))) if value != counter => (value, counter),
+            _ => return None,
+        };
+        let pattern = [
+            Instruction::Jio(value, 8),
+            Instruction::Inc(counter),
+            Instruction::Jie(value, 4),
+            Instruction::Tpl(value),
+            Instruction::Inc(value),
+            Instruction::Jmp(2),
+            Instruction::Hlf(value),
+            Instruction::Jmp(-7),
+        ];
+        if prog.len() >= header + pattern.len() && prog[header..header + pattern.len()] == pattern[..] {
+            Some(Collatz { header: header, value: value, counter: counter })
+        } else {
+            None
+        }
+    }
+}
+
+/// Control flow graph of a program
+pub struct Cfg<'a> {
+    prog: &'a [Instruction],
+    blocks: Vec<Block>,
+}
+
+impl<'a> Cfg<'a> {
+    /// Addresses that can follow the instruction at the given address, and
+    /// whether it can also jump by a register value
+    fn successors(prog: &[Instruction], ip: usize) -> (Vec<usize>, bool) {
+        let target = |ofs: isize| {
+            let target = ip as isize + ofs;
+            if target < 0 { vec![] } else { vec![(target as usize).min(prog.len())] }
+        };
+        let mut next = vec![ip + 1];
+        match prog[ip] {
+            Instruction::Jmp(ofs) => (target(ofs), false),
+            Instruction::Jie(_, ofs) | Instruction::Jio(_, ofs) => { next.extend(target(ofs)); (next, false) },
+            Instruction::Jnz(Operand::Imm(0), _) => (next, false),
+            Instruction::Jnz(Operand::Imm(_), Operand::Imm(ofs)) => (target(ofs), false),
+            Instruction::Jnz(Operand::Reg(_), Operand::Imm(ofs)) => { next.extend(target(ofs)); (next, false) },
+            Instruction::Jnz(_, Operand::Reg(_)) => (next, true),
+            _ => (next, false),
+        }
+    }
+
+    pub fn new(prog: &[Instruction]) -> Cfg {
+        let mut leaders = HashSet::new();
+        leaders.insert(0);
+        for ip in 0..prog.len() {
+            let (successors, indirect) = Cfg::successors(prog, ip);
+            if indirect || successors != vec![ip + 1] {
+                leaders.insert(ip + 1);
+                leaders.extend(successors);
+            }
+        }
+        let mut leaders: Vec<usize> = leaders.into_iter().filter(|&ip| ip < prog.len()).collect();
+        leaders.sort();
+        let blocks = leaders.iter().enumerate().map(|(i, &start)| {
+            let end = leaders.get(i + 1).cloned().unwrap_or(prog.len());
+            let (successors, indirect) = Cfg::successors(prog, end - 1);
+            Block { start: start, end: end, successors: successors, indirect: indirect }
+        }).collect();
+        Cfg { prog: prog, blocks: blocks }
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.binary_search_by_key(&start, |block| block.start).ok().map(|i| &self.blocks[i])
+    }
+
+    /// Natural loops of blocks reachable from the start of the program,
+    /// ordered by header
+    pub fn loops(&self) -> Vec<Loop> {
+        // Find back edges with a depth first search
+        let mut back_edges = Vec::new();
+        let mut visited = HashSet::new();
+        let mut on_stack = HashSet::new();
+        let mut stack = Vec::new();
+        if !self.blocks.is_empty() {
+            visited.insert(0);
+            on_stack.insert(0);
+            stack.push((0, 0));
+        }
+        while let Some((start, i)) = stack.pop() {
+            match self.block(start).unwrap().successors.get(i) {
+                Some(&next) => {
+                    stack.push((start, i + 1));
+                    if on_stack.contains(&next) {
+                        back_edges.push((start, next));
+                    } else if next < self.prog.len() && visited.insert(next) {
+                        on_stack.insert(next);
+                        stack.push((next, 0));
+                    }
+                },
+                None => { on_stack.remove(&start); },
+            }
+        }
+        // Loop body is everything that reaches the back edge without passing the header
+        let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+        for block in &self.blocks {
+            for &next in &block.successors {
+                predecessors.entry(next).or_insert_with(Vec::new).push(block.start);
+            }
+        }
+        let mut loops: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (tail, header) in back_edges {
+            let body = loops.entry(header).or_insert_with(HashSet::new);
+            body.insert(header);
+            let mut todo = vec![tail];
+            while let Some(start) = todo.pop() {
+                if body.insert(start) {
+                    todo.extend(predecessors.get(&start).into_iter().flat_map(|p| p.iter()));
+                }
+            }
+        }
+        let mut loops: Vec<Loop> = loops.into_iter().map(|(header, body)| {
+            let mut blocks: Vec<usize> = body.into_iter().collect();
+            blocks.sort();
+            Loop { header: header, blocks: blocks }
+        }).collect();
+        loops.sort_by_key(|lp| lp.header);
+        loops
+    }
+
+    /// Loops that compute the Collatz sequence
+    pub fn collatz_loops(&self) -> Vec<Collatz> {
+        self.loops().into_iter().filter_map(|lp| {
+            Collatz::new(self.prog, lp.header).and_then(|collatz| {
+                let end = self.block(*lp.blocks.last().unwrap()).unwrap().end;
+                if lp.blocks[0] == lp.header && end == lp.header + 8 { Some(collatz) } else { None }
+            })
+        }).collect()
+    }
+
+    /// Graph in Graphviz DOT format, with loop headers in bold
+    pub fn to_dot(&self) -> String {
+        let headers: HashSet<usize> = self.loops().into_iter().map(|lp| lp.header).collect();
+        let mut dot = String::new();
+        dot.push_str("digraph program {\n");
+        for block in &self.blocks {
+            let label: String = (block.start..block.end).map(|ip| format!("{}: {}\\l", ip, self.prog[ip])).collect();
+            let style = if headers.contains(&block.start) { ", style=bold" } else { "" };
+            dot.push_str(&format!("    \"{}\" [shape=box{}, label=\"{}\"];\n", block.start, style, label));
+        }
+        if self.blocks.iter().any(|block| block.successors.contains(&self.prog.len())) {
+            dot.push_str(&format!("    \"{}\" [label=\"halt\"];\n", self.prog.len()));
+        }
+        if self.blocks.iter().any(|block| block.indirect) {
+            dot.push_str("    \"?\" [label=\"?\"];\n");
+        }
+        for block in &self.blocks {
+            for next in &block.successors {
+                dot.push_str(&format!("    \"{}\" -> \"{}\";\n", block.start, next));
+            }
+            if block.indirect {
+                dot.push_str(&format!("    \"{}\" -> \"?\" [style=dashed];\n", block.start));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<W> {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint(Register, W, W),
+    InvalidJump(usize),
+    Overflow(usize),
+    Halted,
+}
+
+pub struct Debugger<W> {
+    vm: Vm<W>,
+    breakpoints: HashSet<usize>,
+    watchpoints: BTreeSet<Register>,
+}
+
+named!(address<usize>,
+    map_res!(map_res!(digit, str::from_utf8), FromStr::from_str)
+);
+
+named!(debug_command<(&str, Option<Register>, Option<usize>)>,
+    chain!(
+        cmd: map_res!(is_not!(" "), str::from_utf8) ~
+        reg: opt!(complete!(chain!(space ~ reg: register, || reg))) ~
+        n: opt!(complete!(chain!(space ~ n: address, || n))),
+        || (cmd, reg, n)
+    )
+);
+
+impl<W: Word> Debugger<W> {
+    pub fn new(vm: Vm<W>) -> Debugger<W> {
+        Debugger { vm: vm, breakpoints: HashSet::new(), watchpoints: BTreeSet::new() }
+    }
+
+    pub fn vm(&self) -> &Vm<W> {
+        &self.vm
+    }
+
+    pub fn break_at(&mut self, ip: usize) {
+        self.breakpoints.insert(ip);
+    }
+
+    pub fn clear_break(&mut self, ip: usize) {
+        self.breakpoints.remove(&ip);
+    }
+
+    pub fn watch(&mut self, reg: Register) {
+        self.watchpoints.insert(reg);
+    }
+
+    pub fn unwatch(&mut self, reg: Register) {
+        self.watchpoints.remove(&reg);
+    }
+
+    /// Executes a single instruction. If watched registers change, the first
+    /// of them in alphabetical order is reported, even if the program halted
+    /// with that instruction, in which case the next step reports Halted.
+    pub fn step(&mut self) -> Event<W> {
+        if self.vm.done() {
+            return Event::Halted;
+        }
+        let before: Vec<(Register, W)> = self.watchpoints.iter().map(|&reg| (reg, self.vm.get(reg))).collect();
+        match self.vm.step() {
+            Err(Status::InvalidJump(ip)) => return Event::InvalidJump(ip),
+            Err(Status::Overflow { ip, .. }) => return Event::Overflow(ip),
+            _ => (),
+        }
+        for (reg, old) in before {
+            let new = self.vm.get(reg);
+            if new != old {
+                return Event::Watchpoint(reg, old, new);
+            }
+        }
+        if self.vm.done() {
+            Event::Halted
+        } else {
+            Event::Stepped
+        }
+    }
+
+    /// Executes instructions until a breakpoint is reached, a watched register
+    /// changes or the program halts
+    pub fn cont(&mut self) -> Event<W> {
+        loop {
+            match self.step() {
+                Event::Stepped if self.breakpoints.contains(&self.vm.ip) => return Event::Breakpoint(self.vm.ip),
+                Event::Stepped => (),
+                event => return event,
+            }
+        }
+    }
+
+    /// Program listing with the given number of instructions around the
+    /// instruction pointer, marking the current instruction and breakpoints
+    pub fn listing(&self, context: usize) -> String {
+        let from = if self.vm.ip > context { self.vm.ip - context } else { 0 };
+        let to = (self.vm.ip + context + 1).min(self.vm.prog.len());
+        (from..to).map(|addr| {
+            format!("{}{}{:4}  {}\n",
+                if addr == self.vm.ip { ">" } else { " " },
+                if self.breakpoints.contains(&addr) { "*" } else { " " },
+                addr, self.vm.prog[addr])
+        }).collect()
+    }
+
+    fn registers(&self) -> String {
+        let regs: Vec<String> = self.vm.registers().into_iter().map(|reg| format!("{}={}", reg, self.vm.get(reg))).collect();
+        regs.join(" ")
+    }
+
+    fn describe(&self, event: Event<W>) -> String {
+        match event {
+            Event::Stepped => format!("{}", self.listing(0)),
+            Event::Breakpoint(ip) => format!("breakpoint at {}\n{}", ip, self.listing(0)),
+            Event::Watchpoint(reg, old, new) if self.vm.done() => format!("register {} changed from {} to {}\nhalted with {}\n", reg, old, new, self.registers()),
+            Event::Watchpoint(reg, old, new) => format!("register {} changed from {} to {}\n{}", reg, old, new, self.listing(0)),
+            Event::InvalidJump(ip) => format!("invalid jump at {}\n", ip),
+            Event::Overflow(ip) => format!("overflow at {}\n{}", ip, self.listing(0)),
+            Event::Halted => format!("halted with {}\n", self.registers()),
+        }
+    }
+
+    /// Executes a debugger command line and returns its output, or None if
+    /// the debugger should quit
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let output = match debug_command(line.trim().as_bytes()) {
+            IResult::Done(rest, (cmd, reg, n)) if rest == [] => match (cmd, reg, n) {
+                ("s", None, None) | ("step", None, None) => { let event = self.step(); self.describe(event) },
+                ("c", None, None) | ("continue", None, None) => { let event = self.cont(); self.describe(event) },
+                ("b", None, Some(ip)) | ("break", None, Some(ip)) => { self.break_at(ip); format!("breakpoint set at {}\n", ip) },
+                ("d", None, Some(ip)) | ("delete", None, Some(ip)) => { self.clear_break(ip); format!("breakpoint cleared at {}\n", ip) },
+                ("w", Some(reg), None) | ("watch", Some(reg), None) => { self.watch(reg); format!("watching register {}\n", reg) },
+                ("u", Some(reg), None) | ("unwatch", Some(reg), None) => { self.unwatch(reg); format!("not watching register {}\n", reg) },
+                ("set", Some(reg), Some(value)) => match W::from_isize(value as isize, Overflow::Trap) {
+                    Some(value) => { let output = format!("{} = {}\n", reg, value); self.vm.set(reg, value); output },
+                    None => format!("value {} out of range\n", value),
+                },
+                ("l", None, None) | ("list", None, None) => self.listing(5),
+                ("p", None, None) | ("print", None, None) => format!("ip={} {}\n", self.vm.ip, self.registers()),
+                ("q", None, None) | ("quit", None, None) => return None,
+                _ => format!("unknown command '{}'\n", line.trim()),
+            },
+            _ => format!("unknown command '{}'\n", line.trim()),
+        };
+        Some(output)
+    }
+}
+
+fn debug<W: Word>(vm: Vm<W>) {
+    let mut debugger = Debugger::new(vm);
+    let stdin = io::stdin();
+    print!("{}(dbg) ", debugger.listing(0));
+    io::stdout().flush().unwrap();
+    for line in stdin.lock().lines() {
+        match debugger.execute(&line.unwrap()) {
+            Some(output) => print!("{}(dbg) ", output),
+            None => break,
+        }
+        io::stdout().flush().unwrap();
+    }
+}
+
 fn main() {
-    let mut vm = Vm::from(include_str!("day23.txt"));
-    vm.run();
-    println!("Value of register B after running program: {}", vm.b);
-    let mut vm = Vm::from(include_str!("day23.txt"));
-    vm.a = 1;
-    vm.run();
-    println!("Value of register B after running program if register A starts as 1: {}", vm.b);
+    match env::args().nth(1).as_ref().map(|arg| arg.as_str()) {
+        Some("debug") => return debug(Vm::<usize>::from(include_str!("day23.txt"))),
+        Some("cfg") => return print!("{}", Cfg::new(&program(include_str!("day23.txt").as_bytes()).unwrap().1).to_dot()),
+        Some("disasm") => return print!("{}", disassemble(&program(include_str!("day23.txt").as_bytes()).unwrap().1)),
+        Some("trace") => {
+            let path = env::args().nth(2).unwrap_or_else(|| "day23.trace".to_string());
+            let mut file = File::create(path).unwrap();
+            Vm::<usize>::from(include_str!("day23.txt")).run_traced(usize::MAX, &mut file).unwrap();
+            return;
+        },
+        _ => (),
+    }
+    let mut vm = Vm::<usize>::from(include_str!("day23.txt"));
+    vm.compile().run(&mut vm);
+    println!("Value of register B after running program: {}", vm.get(Register::new('b').unwrap()));
+    let mut vm = Vm::<usize>::from(include_str!("day23.txt"));
+    vm.set(Register::new('a').unwrap(), 1);
+    vm.compile().run(&mut vm);
+    println!("Value of register B after running program if register A starts as 1: {}", vm.get(Register::new('b').unwrap()));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn reg(name: char) -> Register {
+        Register::new(name).unwrap()
+    }
+
     #[test]
     fn parsing() {
         assert_eq!(program(b"inc a\njio a, +2\ntpl a\ninc a").unwrap(), (&b""[..], vec![
-            Instruction::Inc(Register::A),
-            Instruction::Jio(Register::A, 2),
-            Instruction::Tpl(Register::A),
-            Instruction::Inc(Register::A),
+            Instruction::Inc(reg('a')),
+            Instruction::Jio(reg('a'), 2),
+            Instruction::Tpl(reg('a')),
+            Instruction::Inc(reg('a')),
+        ]));
+        assert_eq!(Register::new('z').map(|r| r.to_string()), Some("z".to_owned()));
+        assert_eq!(Register::new('A'), None);
+        assert_eq!(Register::new('{'), None);
+    }
+
+    #[test]
+    fn parsing_extended() {
+        assert_eq!(program(b"cpy 41 a\ninc c\ndec d\njnz a 2\njnz 1 -2\nout b\ncpy a z").unwrap(), (&b""[..], vec![
+            Instruction::Cpy(Operand::Imm(41), reg('a')),
+            Instruction::Inc(reg('c')),
+            Instruction::Dec(reg('d')),
+            Instruction::Jnz(Operand::Reg(reg('a')), Operand::Imm(2)),
+            Instruction::Jnz(Operand::Imm(1), Operand::Imm(-2)),
+            Instruction::Out(Operand::Reg(reg('b'))),
+            Instruction::Cpy(Operand::Reg(reg('a')), reg('z')),
         ]));
     }
 
     #[test]
     fn parsing_complete() {
-        let vm = Vm::from(include_str!("day23.txt"));
+        let vm = Vm::<usize>::from(include_str!("day23.txt"));
         assert_eq!(vm.prog.len(), 49);
     }
 
     #[test]
     fn stepping() {
-        let mut vm = Vm::from("inc a\njio a, +2\ntpl a\ninc a");
-        vm.step();
-        assert_eq!(vm.a, 1);
-        vm.step();
+        let mut vm = Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc a");
+        vm.step().unwrap();
+        assert_eq!(vm.get(reg('a')), 1);
+        vm.step().unwrap();
         assert_eq!(vm.ip, 3);
-        vm.step();
-        assert_eq!(vm.a, 2);
+        vm.step().unwrap();
+        assert_eq!(vm.get(reg('a')), 2);
         assert!(vm.done());
     }
 
+    #[test]
+    fn displaying() {
+        assert_eq!(Instruction::Jio(reg('a'), 2).to_string(), "jio a, +2");
+        assert_eq!(Instruction::Jmp(-7).to_string(), "jmp -7");
+        assert_eq!(Instruction::Hlf(reg('b')).to_string(), "hlf b");
+    }
+
+    #[test]
+    fn disassembling() {
+        let prog = program(b"inc a\njio a, +2\ntpl a\ninc a\njnz a b\njmp +2\njmp -7").unwrap().1;
+        assert_eq!(disassemble(&prog), "      inc a\n      jio a, L3\n      tpl a\nL3:   inc a\n      jnz a b\n      jmp L7\n      jmp -7\nL7:\n");
+        let prog = program(include_str!("day23.txt").as_bytes()).unwrap().1;
+        assert_eq!(assemble(&disassemble(&prog)), Ok(prog));
+    }
+
+    #[test]
+    fn assembling() {
+        let prog = assemble("    cpy 3 c ; counter\nloop: dec c\n  inc a\n  jnz c loop\n  jio a, +2\n\nEnd:").unwrap();
+        assert_eq!(prog, program(b"cpy 3 c\ndec c\ninc a\njnz c -2\njio a, +2").unwrap().1);
+        assert_eq!(assemble("jmp L1"), Err("unknown label L1 on line 1".to_string()));
+        assert_eq!(assemble("L1: inc a\nL1: inc b"), Err("label L1 defined twice on line 2".to_string()));
+        assert_eq!(assemble("inc a\nfoo a"), Err("invalid instruction on line 2: foo a".to_string()));
+    }
+
+    #[test]
+    fn tracing() {
+        let mut vm = Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc b");
+        let mut trace = Vec::new();
+        assert_eq!(vm.run_traced(10, &mut trace).unwrap(), Status::Halted);
+        assert_eq!(str::from_utf8(&trace).unwrap(), "ip\tinstruction\ta\tb\n0\tinc a\t0\t0\n1\tjio a, +2\t1\t0\n3\tinc b\t1\t0\n");
+    }
+
+    #[test]
+    fn building_cfg() {
+        let prog = program(b"inc a\njio a, +2\ntpl a\ninc a\njnz a b\njmp -2").unwrap().1;
+        let cfg = Cfg::new(&prog);
+        assert_eq!(cfg.blocks(), &[
+            Block { start: 0, end: 2, successors: vec![2, 3], indirect: false },
+            Block { start: 2, end: 3, successors: vec![3], indirect: false },
+            Block { start: 3, end: 5, successors: vec![5], indirect: true },
+            Block { start: 5, end: 6, successors: vec![3], indirect: false },
+        ][..]);
+        assert_eq!(cfg.loops(), vec![Loop { header: 3, blocks: vec![3, 5] }]);
+        assert_eq!(cfg.to_dot(), "digraph program {\n    \"0\" [shape=box, label=\"0: inc a\\l1: jio a, +2\\l\"];\n    \"2\" [shape=box, label=\"2: tpl a\\l\"];\n    \"3\" [shape=box, style=bold, label=\"3: inc a\\l4: jnz a b\\l\"];\n    \"5\" [shape=box, label=\"5: jmp -2\\l\"];\n    \"?\" [label=\"?\"];\n    \"0\" -> \"2\";\n    \"0\" -> \"3\";\n    \"2\" -> \"3\";\n    \"3\" -> \"5\";\n    \"3\" -> \"?\" [style=dashed];\n    \"5\" -> \"3\";\n}\n");
+    }
+
+    #[test]
+    fn recognizing_collatz() {
+        let prog = program(include_str!("day23.txt").as_bytes()).unwrap().1;
+        let cfg = Cfg::new(&prog);
+        assert_eq!(cfg.loops(), vec![Loop { header: 41, blocks: vec![41, 42, 44, 47, 48] }]);
+        assert_eq!(cfg.collatz_loops(), vec![Collatz { header: 41, value: reg('a'), counter: reg('b') }]);
+        for &a in &[0, 1] {
+            let mut vm = Vm::<usize>::from(include_str!("day23.txt"));
+            vm.set(reg('a'), a);
+            let mut expected = Vm::<usize>::from(include_str!("day23.txt"));
+            expected.set(reg('a'), a);
+            assert_eq!(vm.run_shortcut(), expected.run());
+            assert_eq!(vm, expected);
+        }
+        let prog = "cpy 27 a\njio a, +8\ninc b\njie a, +4\ntpl a\ninc a\njmp +2\nhlf a\njmp -7";
+        let mut vm = Vm::<u8>::from(prog);
+        let mut expected = Vm::<u8>::from(prog);
+        assert_eq!(vm.run_shortcut(), Status::Overflow { ip: 4, instruction: Instruction::Tpl(reg('a')) });
+        expected.run();
+        assert_eq!(vm, expected);
+        let mut vm = Vm::<u8>::from(prog).with_overflow(Overflow::Wrap);
+        let mut expected = Vm::<u8>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run_shortcut(), expected.run());
+        assert_eq!(vm, expected);
+        let mut vm = Vm::<usize>::from(prog);
+        assert_eq!(vm.run_shortcut(), Status::Halted);
+        assert_eq!(vm.get(reg('b')), 111);
+    }
+
+    #[test]
+    fn debugging() {
+        let mut dbg = Debugger::new(Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc a\ninc b"));
+        dbg.break_at(3);
+        assert_eq!(dbg.step(), Event::Stepped);
+        assert_eq!(dbg.vm().get(reg('a')), 1);
+        assert_eq!(dbg.cont(), Event::Breakpoint(3));
+        dbg.watch(reg('a'));
+        assert_eq!(dbg.cont(), Event::Watchpoint(reg('a'), 1, 2));
+        assert_eq!(dbg.vm().ip, 4);
+        assert_eq!(dbg.listing(1), " *   3  inc a\n>    4  inc b\n");
+        dbg.unwatch(reg('a'));
+        assert_eq!(dbg.cont(), Event::Halted);
+        assert_eq!(dbg.vm().get(reg('b')), 1);
+        assert_eq!(dbg.step(), Event::Halted);
+    }
+
+    #[test]
+    fn debugging_commands() {
+        let mut dbg = Debugger::new(Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc a"));
+        assert_eq!(dbg.execute("set a 5"), Some("a = 5\n".to_owned()));
+        assert_eq!(dbg.execute("b 2"), Some("breakpoint set at 2\n".to_owned()));
+        assert_eq!(dbg.execute("c"), Some("breakpoint at 2\n>*   2  tpl a\n".to_owned()));
+        assert_eq!(dbg.execute("w a"), Some("watching register a\n".to_owned()));
+        assert_eq!(dbg.execute("s"), Some("register a changed from 6 to 18\n>    3  inc a\n".to_owned()));
+        assert_eq!(dbg.execute("p"), Some("ip=3 a=18\n".to_owned()));
+        assert_eq!(dbg.execute("frobnicate"), Some("unknown command 'frobnicate'\n".to_owned()));
+        assert_eq!(dbg.execute("c"), Some("register a changed from 18 to 19\nhalted with a=19\n".to_owned()));
+        assert_eq!(dbg.execute("c"), Some("halted with a=19\n".to_owned()));
+        assert_eq!(dbg.execute("q"), None);
+        let mut dbg = Debugger::new(Vm::<usize>::from("inc b\ninc a\ninc b\ninc c"));
+        dbg.watch(reg('c'));
+        dbg.watch(reg('b'));
+        dbg.watch(reg('a'));
+        assert_eq!(dbg.cont(), Event::Watchpoint(reg('b'), 0, 1));
+        assert_eq!(dbg.cont(), Event::Watchpoint(reg('a'), 0, 1));
+        assert_eq!(dbg.cont(), Event::Watchpoint(reg('b'), 1, 2));
+        assert_eq!(dbg.cont(), Event::Watchpoint(reg('c'), 0, 1));
+        assert_eq!(dbg.cont(), Event::Halted);
+    }
+
     #[test]
     fn running() {
-        let mut vm = Vm::from("inc a\njio a, +2\ntpl a\ninc a");
-        vm.run();
-        assert_eq!(vm.a, 2);
+        let mut vm = Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc a");
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 2);
         assert!(vm.done());
     }
+
+    #[test]
+    fn running_extended() {
+        let mut vm = Vm::<usize>::from("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a");
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 42);
+        let mut vm = Vm::<usize>::from("cpy 3 c\ncpy c d\nout d\ndec c\njnz c -3\nout 7");
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.output, vec![3, 2, 1, 7]);
+        assert_eq!(vm.registers(), vec![reg('c'), reg('d')]);
+        assert_eq!(Instruction::Jnz(Operand::Reg(reg('c')), Operand::Imm(-3)).to_string(), "jnz c -3");
+        assert_eq!(Instruction::Cpy(Operand::Imm(3), reg('c')).to_string(), "cpy 3 c");
+    }
+
+    #[test]
+    fn overflowing() {
+        let prog = "cpy 200 a\ntpl a\ninc a\ndec b";
+        let mut vm = Vm::<u32>::from(prog);
+        assert_eq!(vm.run(), Status::Overflow { ip: 3, instruction: Instruction::Dec(reg('b')) });
+        assert_eq!(vm.get(reg('a')), 601);
+        let mut vm = Vm::<u32>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('b')), u32::max_value());
+        let mut vm = Vm::<u32>::from(prog).with_overflow(Overflow::Saturate);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('b')), 0);
+        let prog = "inc a\ntpl a\njio b, +2\njmp -2";
+        let mut vm = Vm::<u32>::from(prog);
+        assert_eq!(vm.run(), Status::Overflow { ip: 1, instruction: Instruction::Tpl(reg('a')) });
+        assert_eq!(vm.get(reg('a')), 3u32.pow(20));
+        let mut vm = Vm::<u32>::from(prog).with_overflow(Overflow::Saturate);
+        assert_eq!(vm.run_with_limit(1000), Status::StepLimit);
+        assert_eq!(vm.get(reg('a')), u32::max_value());
+        let mut vm = Vm::<u64>::from(prog);
+        assert_eq!(vm.run(), Status::Overflow { ip: 1, instruction: Instruction::Tpl(reg('a')) });
+        assert_eq!(vm.get(reg('a')), 3u64.pow(40));
+        let mut vm = Vm::<u128>::from(prog);
+        assert_eq!(vm.run(), Status::Overflow { ip: 1, instruction: Instruction::Tpl(reg('a')) });
+        assert_eq!(vm.get(reg('a')), 3u128.pow(80));
+        let mut vm = Vm::<BigUint>::from(prog);
+        assert_eq!(vm.run_with_limit(300), Status::StepLimit);
+        assert_eq!(vm.get(reg('a')), num_traits::pow(BigUint::from(3u32), 100));
+        let mut vm = Vm::<BigUint>::from("dec a");
+        assert_eq!(vm.run(), Status::Overflow { ip: 0, instruction: Instruction::Dec(reg('a')) });
+    }
+
+    #[test]
+    fn register_offsets() {
+        let prog = "cpy 2 b\ncpy -2 c\ninc a\ndec b\njnz b c";
+        let mut vm = Vm::<u8>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 2);
+        let mut vm = Vm::<u32>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 2);
+        let mut vm = Vm::<u64>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 2);
+        let mut vm = Vm::<u128>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 2);
+        // Unbounded words can't hold -2, it saturates to a jump by zero
+        let mut vm = Vm::<BigUint>::from(prog).with_overflow(Overflow::Wrap);
+        assert_eq!(vm.run_with_limit(1000), Status::StepLimit);
+        assert_eq!(vm.get(reg('c')), BigUint::zero());
+        let mut vm = Vm::<u64>::from("jnz 1 c");
+        vm.set(reg('c'), 1 << 63);
+        assert_eq!(vm.run(), Status::InvalidJump(0));
+        let mut vm = Vm::<u128>::from("jnz 1 c");
+        vm.set(reg('c'), 1 << 100);
+        assert_eq!(vm.run(), Status::InvalidJump(0));
+        let mut vm = Vm::<BigUint>::from("jnz 1 c");
+        vm.set(reg('c'), BigUint::one() << 100);
+        assert_eq!(vm.run(), Status::InvalidJump(0));
+        let mut vm = Vm::<BigUint>::from("jnz 1 c\ninc a");
+        vm.set(reg('c'), BigUint::from(2u32));
+        assert_eq!(vm.run(), Status::Halted);
+        assert_eq!(vm.get(reg('a')), BigUint::zero());
+    }
+
+    #[test]
+    fn running_bigint() {
+        let mut vm = Vm::<BigUint>::from(include_str!("day23.txt"));
+        assert_eq!(vm.run(), Status::Halted);
+        let mut expected = Vm::<usize>::from(include_str!("day23.txt"));
+        expected.run();
+        assert_eq!(vm.get(reg('b')).to_string(), expected.get(reg('b')).to_string());
+    }
+
+    #[test]
+    fn running_compiled() {
+        let programs = [
+            "inc a\njio a, +2\ntpl a\ninc a",
+            "cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a",
+            "cpy 3 c\ncpy c d\nout d\ndec c\njnz c -3\nout 7",
+            "cpy 2 b\ncpy -2 c\njnz b c\njnz 1 -5",
+            "inc a\njmp -2",
+            "cpy 200 a\ntpl a\ninc a\ndec b",
+            "inc a\ntpl a\njio b, +2\njmp -2",
+            "jmp +5",
+            "inc a\njie a, +7\njio a, +3",
+            "jnz -1 2\ninc a",
+            "jnz 7 3\ninc a",
+            include_str!("day23.txt"),
+        ];
+        for prog in programs.iter() {
+            for &overflow in &[Overflow::Wrap, Overflow::Saturate, Overflow::Trap] {
+                for &a in &[0, 1] {
+                    let mut vm = Vm::<u32>::from(*prog).with_overflow(overflow);
+                    vm.set(reg('a'), a);
+                    let mut expected = Vm::<u32>::from(*prog).with_overflow(overflow);
+                    expected.set(reg('a'), a);
+                    let compiled = vm.compile();
+                    assert_eq!(compiled.run_with_limit(&mut vm, 10000), expected.run_with_limit(10000));
+                    assert_eq!(vm, expected);
+                }
+            }
+        }
+        for limit in 0..20 {
+            let mut vm = Vm::<u32>::from(programs[2]);
+            let mut expected = Vm::<u32>::from(programs[2]);
+            assert_eq!(vm.compile().run_with_limit(&mut vm, limit), expected.run_with_limit(limit));
+            assert_eq!(vm, expected);
+        }
+        let mut vm = Vm::<usize>::from("cpy 1000000 a\ndec a\njnz a -1");
+        assert_eq!(vm.compile().run(&mut vm), Status::Halted);
+        assert_eq!(vm.get(reg('a')), 0);
+    }
+
+    #[test]
+    fn running_with_limit() {
+        let mut vm = Vm::<usize>::from("inc a\njio a, +2\ntpl a\ninc a");
+        assert_eq!(vm.run_with_limit(2), Status::StepLimit);
+        assert_eq!(vm.ip, 3);
+        assert_eq!(vm.run_with_limit(2), Status::Halted);
+        let mut vm = Vm::<usize>::from("inc a\njmp -1");
+        assert_eq!(vm.run_with_limit(1000), Status::StepLimit);
+        assert_eq!(vm.get(reg('a')), 500);
+        let mut vm = Vm::<usize>::from("inc a\njmp -2");
+        assert_eq!(vm.run_with_limit(1000), Status::InvalidJump(1));
+        assert_eq!(vm.ip, 1);
+    }
+
+    #[test]
+    fn detecting_cycles() {
+        let mut vm = Vm::<usize>::from("inc a\nhlf a\njio b, +2\njmp -2\ninc b");
+        assert_eq!(vm.run_until_cycle(1000), Status::Cycle { ip: 2, period: 3 });
+        let mut vm = Vm::<usize>::from("inc a\njmp -1");
+        assert_eq!(vm.run_until_cycle(1000), Status::StepLimit);
+        let mut vm = Vm::<usize>::from(include_str!("day23.txt"));
+        assert_eq!(vm.run_until_cycle(100000), Status::Halted);
+    }
 }